@@ -1,35 +1,40 @@
 use std::{
     cmp::{max, min},
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     io::{self, stdout, ErrorKind, Read, Write},
-    net::{TcpStream, Shutdown},
+    net::TcpStream,
     process::exit,
     sync::{
         mpsc::{channel, Sender},
         Arc, Mutex, RwLock,
     },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use crossterm::{
     cursor::{Hide, MoveTo},
     event::{poll, read, Event, KeyCode, KeyModifiers},
-    style::{PrintStyledContent, StyledContent, Stylize},
+    style::{Color, PrintStyledContent, StyledContent, Stylize},
     terminal::{self, Clear, ClearType},
     QueueableCommand,
 };
 use game_core::{
-    constants::{LOCAL_HOST, PORT},
-    protocol::{self, ClientPacket, Direction, OtherPlayerMoved, Packet, ServerPacket},
-    types::{Block, Coords, MapCell},
+    constants::{LOCAL_HOST, MAX_CHAT_LEN, MAX_NAME_LEN, PORT, SERVER_ID},
+    protocol::{
+        self, ChatChannel, ChatMessage, ClientPacket, FlagState, FlagStatus, GameMode, Inventory,
+        KillFeed, MatchPhase, MatchResults, OtherPlayerMoved, Packet, Projectile, RejectReason,
+        Score, ServerPacket, ServerShutdown, ShotFired, TeamScores, WeaponState, Winner,
+    },
+    sim::MOVE_COOLDOWN,
+    types::{Block, Direction, ItemKind, MapCell, Position, Team, Vector},
     utils,
+    weapons::WeaponKind,
 };
 use logger::{log, log_error, log_info};
 use proto_dryb::{Deserialize, Serialize};
 
 #[allow(unused_macros)]
-
 macro_rules! print_to_file {
     ($($arg:tt)*) => {{
         use std::fs::OpenOptions;
@@ -47,7 +52,7 @@ macro_rules! print_to_file {
 }
 
 // https://www.patorjk.com/software/taag/#p=display&f=ANSI%20Regular&t=BABOOGEE
-const LOGO: &'static str = r#"
+const LOGO: &str = r#"
 ██████   █████  ██████   ██████   ██████   ██████  ███████ ███████ 
 ██   ██ ██   ██ ██   ██ ██    ██ ██    ██ ██       ██      ██      
 ██████  ███████ ██████  ██    ██ ██    ██ ██   ███ █████   █████   
@@ -55,7 +60,7 @@ const LOGO: &'static str = r#"
 ██████  ██   ██ ██████   ██████   ██████   ██████  ███████ ███████ 
 "#;
 
-const KILLED_YOU: &'static str = r#"
+const KILLED_YOU: &str = r#"
 ██   ██ ██ ██      ██      ███████ ██████      ██    ██  ██████  ██    ██ 
 ██  ██  ██ ██      ██      ██      ██   ██      ██  ██  ██    ██ ██    ██ 
 █████   ██ ██      ██      █████   ██   ██       ████   ██    ██ ██    ██ 
@@ -63,7 +68,7 @@ const KILLED_YOU: &'static str = r#"
 ██   ██ ██ ███████ ███████ ███████ ██████         ██     ██████   ██████  
 "#;
 
-const ONE: &'static str = r#"
+const ONE: &str = r#"
  ██
 ███
  ██
@@ -71,7 +76,7 @@ const ONE: &'static str = r#"
  ██
 "#;
 
-const TWO: &'static str = r#"
+const TWO: &str = r#"
 ██████  
      ██ 
  █████  
@@ -79,7 +84,7 @@ const TWO: &'static str = r#"
 ███████
 "#;
 
-const THREE: &'static str = r#"
+const THREE: &str = r#"
 ██████ 
      ██
  █████ 
@@ -87,7 +92,7 @@ const THREE: &'static str = r#"
 ██████ 
 "#;
 
-const FOUR: &'static str = r#"
+const FOUR: &str = r#"
 ██   ██
 ██   ██
 ███████
@@ -95,7 +100,7 @@ const FOUR: &'static str = r#"
      ██
 "#;
 
-const FIVE: &'static str = r#"
+const FIVE: &str = r#"
 ███████
 ██     
 ███████
@@ -103,7 +108,7 @@ const FIVE: &'static str = r#"
 ███████
 "#;
 
-const SIX: &'static str = r#"
+const SIX: &str = r#"
  ██████ 
 ██      
 ███████ 
@@ -111,7 +116,7 @@ const SIX: &'static str = r#"
  ██████ 
 "#;
 
-const SEVEN: &'static str = r#"
+const SEVEN: &str = r#"
 ███████
      ██
     ██ 
@@ -119,7 +124,7 @@ const SEVEN: &'static str = r#"
    ██
 "#;
 
-const EIGHT: &'static str = r#"
+const EIGHT: &str = r#"
  █████ 
 ██   ██
  █████ 
@@ -127,7 +132,7 @@ const EIGHT: &'static str = r#"
  █████ 
 "#;
 
-const NINE: &'static str = r#"
+const NINE: &str = r#"
  █████ 
 ██   ██
  ██████
@@ -135,6 +140,17 @@ const NINE: &'static str = r#"
  █████ 
 "#;
 
+const KILL_FEED_LEN: usize = 5;
+const CHAT_HISTORY_LEN: usize = 5;
+const STATUS_LINE_TIME: Duration = Duration::from_secs(2);
+// the wait doubles after every failed attempt to reconnect, up to the max
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(250);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(8);
+const RECONNECT_ATTEMPTS: u32 = 10;
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+// packets per second are counted over this long
+const PACKET_RATE_WINDOW: Duration = Duration::from_secs(1);
+
 // TODO hack bcs cannot impl types from other crates
 struct BlockWrapper(Block);
 impl From<BlockWrapper> for StyledContent<char> {
@@ -150,106 +166,496 @@ impl From<BlockWrapper> for StyledContent<char> {
             Block::WallTopRight => '┓'.grey(),
             Block::WallBottomLeft => '┗'.grey(),
             Block::WallBottomRight => '┛'.grey(),
+            Block::Item(ItemKind::HealthPack) => '+'.green(),
+            Block::Item(ItemKind::Ammo) => '='.yellow(),
+            Block::Item(ItemKind::Armor) => ']'.cyan(),
+            Block::Item(ItemKind::Weapon(kind)) => {
+                kind.spec().name.chars().next().unwrap().magenta()
+            }
+            Block::Flag(team) => 'F'.with(team_color(Some(team))),
         }
     }
 }
 
+/// Without teams everybody else is an enemy
+fn team_color(team: Option<Team>) -> Color {
+    match team {
+        None | Some(Team::Red) => Color::Red,
+        Some(Team::Blue) => Color::Blue,
+    }
+}
+
+fn team_name_color(team: Option<Team>) -> Color {
+    match team {
+        None | Some(Team::Red) => Color::DarkRed,
+        Some(Team::Blue) => Color::DarkBlue,
+    }
+}
+
 struct Player {
-    coords: Coords,
+    coords: Position,
+    name: String,
+    team: Option<Team>,
+}
+
+fn player_entry(p: protocol::Player) -> (u32, Player) {
+    (
+        p.id,
+        Player {
+            coords: p.coords,
+            name: p.name,
+            team: p.team,
+        },
+    )
 }
 
 struct Client {
     id: u32,
+    name: String,
     stream: Option<TcpStream>,
-    coords: Coords,
+    // token of the player on the server, to resume it after losing the connection
+    session: Option<u64>,
+    // Some while the connection is lost, when to try to get it back next
+    reconnect_at: Option<Instant>,
+    reconnect_attempts: u32,
+    // why the server closed the connection, if it said so before
+    farewell: Option<ServerShutdown>,
+    // nonce of the last ping, older pongs are ignored
+    ping_nonce: u32,
+    next_ping_at: Instant,
+    // round trip time measured by the last pong
+    latency: Option<Duration>,
+    // packets received in the current window and the count of the previous one
+    packets_received: u32,
+    packet_rate: u32,
+    packet_window_ends_at: Instant,
+    // predicted, moves are made right away and taken back if the server refuses them
+    coords: Position,
+    // sequence number of the last move sent
+    move_seq: u32,
+    // steps are paced MOVE_COOLDOWN apart from this one, the server lets them arrive a bit early
+    last_move_at: Option<Instant>,
+    // moves made on this side which the server has not answered yet
+    pending_moves: VecDeque<(u32, Direction)>,
     visible_map: Vec<MapCell>,
     other_players: HashMap<u32, Player>,
     players_outside: HashMap<u32, Player>,
+    // shots in flight inside the view, everyone's
+    projectiles: HashMap<u32, Projectile>,
     radius: u8,
     max_hp: u8,
     current_hp: u8,
     weapon: Weapon,
-    shooting_angle: Direction,
+    inventory: Inventory,
+    // where shots go, independent of the movement
+    aim: Direction,
+    respawn_at: Option<Instant>,
+    kill_feed: VecDeque<KillFeed>,
+    scoreboard: Vec<Score>,
+    show_scoreboard: bool,
+    scoreboard_stale: bool,
+    chat_history: VecDeque<ChatMessage>,
+    // Some while the chat input line is open
+    chat_input: Option<String>,
+    chat_channel: ChatChannel,
+    mode: GameMode,
+    team: Option<Team>,
+    team_scores: TeamScores,
+    flags: Vec<FlagStatus>,
+    match_phase: MatchPhase,
+    match_ends_at: Option<Instant>,
+    // (connected, needed to start)
+    match_players: (u8, u8),
+    // Some while the post match results are shown
+    match_results: Option<MatchResults>,
+    // why the last action was refused, shown until the instant
+    status: Option<(RejectReason, Instant)>,
     quit: bool,
 }
 
+// Mirror of the server side weapon, ready_at is only a local guess to avoid spamming shots
 #[derive(Default)]
 struct Weapon {
-    range: u8,
+    kind: WeaponKind,
+    ammo: u8,
+    reserve: u8,
+    reloading: bool,
+    ready_at: Option<Instant>,
+}
+
+impl Weapon {
+    fn can_fire(&self) -> bool {
+        !self.reloading
+            && self.ammo > 0
+            && self
+                .ready_at
+                .is_none_or(|ready_at| Instant::now() >= ready_at)
+    }
+
+    fn fired(&mut self) {
+        self.ammo = self.ammo.saturating_sub(1);
+        self.ready_at = Some(Instant::now() + self.kind.spec().cooldown);
+    }
+
+    fn update(&mut self, state: WeaponState) {
+        if state.kind != self.kind {
+            self.ready_at = None;
+        }
+        self.kind = state.kind;
+        self.ammo = state.ammo;
+        self.reserve = state.reserve;
+        self.reloading = state.reloading;
+    }
 }
 
 impl Default for Client {
     fn default() -> Self {
         Self {
             radius: 5,
-            shooting_angle: Direction::Up,
+            aim: Direction::Up,
 
             id: 0,
+            name: String::new(),
             max_hp: 0,
             current_hp: 0,
             quit: false,
+            respawn_at: None,
+            kill_feed: VecDeque::with_capacity(KILL_FEED_LEN),
+            scoreboard: vec![],
+            show_scoreboard: false,
+            scoreboard_stale: false,
+            chat_history: VecDeque::with_capacity(CHAT_HISTORY_LEN),
+            chat_input: None,
+            chat_channel: ChatChannel::Proximity,
+            mode: GameMode::default(),
+            team: None,
+            team_scores: TeamScores::default(),
+            flags: vec![],
+            match_phase: MatchPhase::default(),
+            match_ends_at: None,
+            match_players: (0, 0),
+            match_results: None,
+            status: None,
             stream: None,
+            session: None,
+            reconnect_at: None,
+            reconnect_attempts: 0,
+            farewell: None,
+            ping_nonce: 0,
+            next_ping_at: Instant::now(),
+            latency: None,
+            packets_received: 0,
+            packet_rate: 0,
+            packet_window_ends_at: Instant::now() + PACKET_RATE_WINDOW,
             visible_map: vec![],
             other_players: HashMap::default(),
             players_outside: HashMap::default(),
+            projectiles: HashMap::default(),
             weapon: Weapon::default(),
+            inventory: Inventory {
+                armor: 0,
+                health_packs: 0,
+                weapons: vec![],
+            },
             coords: Default::default(),
+            move_seq: 0,
+            last_move_at: None,
+            pending_moves: VecDeque::new(),
         }
     }
 }
 
 impl Client {
     fn send_move(&mut self, buf: &mut [u8], x: char) -> Result<(), ()> {
-        let packet_to_send = Packet::Client(ClientPacket::Move(Direction::try_from(x)?));
+        let direction = Direction::try_from(x)?;
+        // key repeat is faster than the cooldown, the extra steps are dropped here
+        if self
+            .last_move_at
+            .is_some_and(|at| at.elapsed() < MOVE_COOLDOWN)
+        {
+            return Ok(());
+        }
+        self.last_move_at = Some(Instant::now());
+        self.move_seq += 1;
+        let packet_to_send = Packet::Client(ClientPacket::Move(self.move_seq, direction));
 
         let n = packet_to_send.serialize(buf).map_err(|_| ())?;
-        if let Some(stream) = self.stream.as_mut() {
-            stream.write(&mut buf[..n]).map_err(|_| ())?;
+        self.write(&buf[..n]);
+
+        // steps the server is bound to refuse are not predicted, it gets to say where we are
+        if let Some(coords) = self.predict_step(self.coords, direction) {
+            self.coords = coords;
+            self.pending_moves.push_back((self.move_seq, direction));
         }
 
         Ok(())
     }
 
     fn send_shoot(&mut self, buf: &mut [u8]) -> Result<(), ()> {
-        let packet_to_send = Packet::Client(ClientPacket::Shoot(self.shooting_angle));
+        let packet_to_send = Packet::Client(ClientPacket::Shoot(self.aim));
 
         let n = packet_to_send.serialize(buf).map_err(|_| ())?;
-        if let Some(stream) = self.stream.as_mut() {
-            stream.write(&mut buf[..n]).map_err(|_| ())?;
-        }
+        self.write(&buf[..n]);
+
+        Ok(())
+    }
+
+    fn send_resume(&mut self, buf: &mut [u8], token: u64) -> Result<(), ()> {
+        let packet_to_send = Packet::Client(ClientPacket::Resume(token, self.name.clone()));
+
+        let n = packet_to_send.serialize(buf).map_err(|_| ())?;
+        self.write(&buf[..n]);
+
+        Ok(())
+    }
+
+    fn send_admin(&mut self, buf: &mut [u8], password: String, command: String) -> Result<(), ()> {
+        let packet_to_send = Packet::Client(ClientPacket::Admin(password, command));
+
+        let n = packet_to_send.serialize(buf).map_err(|_| ())?;
+        self.write(&buf[..n]);
+
+        Ok(())
+    }
+
+    fn send_ping(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+        self.ping_nonce = self.ping_nonce.wrapping_add(1);
+        let packet_to_send = Packet::Client(ClientPacket::Ping(
+            self.ping_nonce,
+            utils::timestamp_millis(),
+        ));
+
+        let n = packet_to_send.serialize(buf).map_err(|_| ())?;
+        self.write(&buf[..n]);
+
+        Ok(())
+    }
+
+    fn send_pong(&mut self, buf: &mut [u8], nonce: u32) -> Result<(), ()> {
+        let packet_to_send = Packet::Client(ClientPacket::Pong(nonce));
+
+        let n = packet_to_send.serialize(buf).map_err(|_| ())?;
+        self.write(&buf[..n]);
+
+        Ok(())
+    }
+
+    fn send_join(&mut self, buf: &mut [u8], name: String) -> Result<(), ()> {
+        let packet_to_send = Packet::Client(ClientPacket::Join(name));
+
+        let n = packet_to_send.serialize(buf).map_err(|_| ())?;
+        self.write(&buf[..n]);
+
+        Ok(())
+    }
+
+    fn send_chat(&mut self, buf: &mut [u8], text: String) -> Result<(), ()> {
+        let packet_to_send = Packet::Client(ClientPacket::Chat(self.chat_channel, text));
+
+        let n = packet_to_send.serialize(buf).map_err(|_| ())?;
+        self.write(&buf[..n]);
+
+        Ok(())
+    }
+
+    fn send_scoreboard_request(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+        let packet_to_send = Packet::Client(ClientPacket::Scoreboard);
+
+        let n = packet_to_send.serialize(buf).map_err(|_| ())?;
+        self.write(&buf[..n]);
+
+        Ok(())
+    }
+
+    fn send_switch_weapon(&mut self, buf: &mut [u8], kind: WeaponKind) -> Result<(), ()> {
+        let packet_to_send = Packet::Client(ClientPacket::SwitchWeapon(kind));
+
+        let n = packet_to_send.serialize(buf).map_err(|_| ())?;
+        self.write(&buf[..n]);
+
+        Ok(())
+    }
+
+    fn send_reload(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+        let packet_to_send = Packet::Client(ClientPacket::Reload);
+
+        let n = packet_to_send.serialize(buf).map_err(|_| ())?;
+        self.write(&buf[..n]);
+
+        Ok(())
+    }
+
+    fn send_use_health_pack(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+        let packet_to_send = Packet::Client(ClientPacket::UseHealthPack);
+
+        let n = packet_to_send.serialize(buf).map_err(|_| ())?;
+        self.write(&buf[..n]);
 
         Ok(())
     }
 }
 
 impl Client {
-    fn connect(&mut self, ip: &str, port: u16) {
+    fn connect(&mut self, ip: &str, port: u16) -> io::Result<()> {
         if self.stream.is_none() {
-            self.stream = TcpStream::connect(format!("{ip}:{port}"))
-                .and_then(|stream| {
-                    stream.set_nonblocking(true)?;
-                    Ok(stream)
-                })
-                .map_err(|err| eprintln!("Could not connect to {ip}:{port}, {err}"))
-                .ok();
-        } else {
-            eprintln!("Already connected to server")
+            let stream = TcpStream::connect(format!("{ip}:{port}"))?;
+            stream.set_nonblocking(true)?;
+            self.stream = Some(stream);
+        }
+
+        Ok(())
+    }
+
+    /// A failed write is taken as a lost connection
+    fn write(&mut self, bytes: &[u8]) {
+        if let Some(stream) = self.stream.as_mut() {
+            if stream.write(bytes).is_err() {
+                self.connection_lost();
+            }
+        }
+    }
+
+    fn connection_lost(&mut self) {
+        self.stream = None;
+        self.pending_moves.clear();
+        self.latency = None;
+
+        let now = Instant::now();
+        self.reconnect_at = Some(now);
+        match self.farewell.as_ref().map(|farewell| farewell.reconnect_in) {
+            // no use knocking before the server is back
+            Some(Some(secs)) => {
+                self.reconnect_at = Some(now + Duration::from_secs(secs as u64));
+                self.reconnect_attempts = 0;
+            }
+            Some(None) => self.reconnect_attempts = RECONNECT_ATTEMPTS,
+            None => {}
+        }
+    }
+
+    /// Why the client gives up on the server
+    fn disconnect_reason(&self) -> String {
+        match &self.farewell {
+            Some(farewell) => farewell.reason.clone(),
+            None => "Could not reconnect to the server".to_string(),
+        }
+    }
+
+    /// Keeps the server hearing from us while nothing else is sent and closes the packet rate
+    /// window once it is over
+    fn heartbeat(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+        let now = Instant::now();
+
+        if now >= self.packet_window_ends_at {
+            self.packet_window_ends_at = now + PACKET_RATE_WINDOW;
+            self.packet_rate = self.packets_received;
+            self.packets_received = 0;
+        }
+
+        if self.stream.is_some() && now >= self.next_ping_at {
+            self.next_ping_at = now + PING_INTERVAL;
+            self.send_ping(buf)?;
+        }
+
+        Ok(())
+    }
+
+    /// Connects again once it is time to, resuming the player if the server has ever given us
+    /// one. Err once every attempt has failed
+    fn try_reconnect(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+        if self.reconnect_at.is_none_or(|at| Instant::now() < at) {
+            return Ok(());
+        }
+        if self.reconnect_attempts >= RECONNECT_ATTEMPTS {
+            return Err(());
+        }
+
+        self.reconnect_attempts += 1;
+        if self.connect(LOCAL_HOST, PORT).is_err() {
+            let backoff = RECONNECT_BACKOFF * 2u32.pow(self.reconnect_attempts - 1);
+            self.reconnect_at = Some(Instant::now() + min(backoff, RECONNECT_BACKOFF_MAX));
+            return Ok(());
+        }
+
+        self.reconnect_at = None;
+        match self.session {
+            Some(token) => self.send_resume(buf, token),
+            None => self.send_join(buf, self.name.clone()),
         }
     }
 
+    /// Where a step leads going by the visible map and the players around, the same rules the
+    /// server moves players by. None if the server would refuse it
+    fn predict_step(&self, from: Position, direction: Direction) -> Option<Position> {
+        let block_at = |coords: Position| {
+            self.visible_map
+                .iter()
+                .find(|cell| cell.coords == coords)
+                .map(|cell| cell.block)
+        };
+        // the map size is not known here, cells off the map are never in the visible map
+        let to = from.offset(direction.delta(), u16::MAX as usize, u16::MAX as usize)?;
+
+        if block_at(to)?.is_blocking() {
+            return None;
+        }
+        let cuts_corner = direction.is_diagonal()
+            && [
+                Position::new(to.row, from.col),
+                Position::new(from.row, to.col),
+            ]
+            .into_iter()
+            .all(|corner| block_at(corner).is_some_and(|block| block.is_blocking()));
+        if cuts_corner || self.other_players.values().any(|p| p.coords == to) {
+            return None;
+        }
+
+        Some(to)
+    }
+
+    /// Moves on from the position the server confirmed with the moves it has not answered yet,
+    /// whatever it refused is undone that way
+    fn reconcile(&mut self, center: Position, ack: u32) {
+        self.pending_moves.retain(|&(seq, _)| seq > ack);
+
+        let mut coords = center;
+        for (seq, direction) in std::mem::take(&mut self.pending_moves) {
+            if let Some(next) = self.predict_step(coords, direction) {
+                coords = next;
+                self.pending_moves.push_back((seq, direction));
+            }
+        }
+        self.coords = coords;
+    }
+
     fn remove_non_visible(&mut self) {
-        self.visible_map.retain(|&MapCell { coords: (x, y), .. }| {
-            (x as i16 - self.coords.0 as i16).pow(2) + (y as i16 - self.coords.1 as i16).pow(2)
-                <= (self.radius as i16).pow(2)
-        });
+        self.visible_map
+            .retain(|cell| utils::is_inside_circle(self.coords, self.radius, cell.coords));
+        self.projectiles
+            .retain(|_, p| utils::is_inside_circle(self.coords, self.radius, p.coords));
     }
 
-    fn update_other_player_coords_after_move(&mut self, players: &Vec<protocol::Player>) {
-        for &protocol::Player { id, coords } in players.iter() {
+    /// Projectiles are only kept while they fly inside the view
+    fn update_projectile(&mut self, projectile: Projectile) {
+        if utils::is_inside_circle(self.coords, self.radius, projectile.coords) {
+            self.projectiles.insert(projectile.id, projectile);
+        } else {
+            self.projectiles.remove(&projectile.id);
+        }
+    }
+
+    fn update_other_player_coords_after_move(&mut self, players: Vec<protocol::Player>) {
+        for protocol::Player {
+            id,
+            coords,
+            name,
+            team,
+        } in players
+        {
             self.other_players
                 .entry(id)
                 .and_modify(|p| p.coords = coords)
-                .or_insert(Player { coords });
+                .or_insert(Player { coords, name, team });
 
             self.players_outside.remove(&id);
         }
@@ -271,72 +677,190 @@ impl Client {
         }
     }
 
-    fn update_other_player_coords_after_other_player_move(&mut self, id: u32, coords: Coords) {
+    fn update_other_player_coords_after_other_player_move(
+        &mut self,
+        id: u32,
+        coords: Position,
+        name: String,
+        team: Option<Team>,
+    ) {
         self.players_outside.remove(&id);
 
         self.other_players
             .entry(id)
             .and_modify(|p| p.coords = coords)
-            .or_insert(Player { coords });
+            .or_insert(Player { coords, name, team });
     }
 
     fn remove_player(&mut self, id: u32) {
         self.other_players.remove(&id);
         self.players_outside.remove(&id);
     }
-}
 
-fn get_padding(a: Coords, b: Coords) -> (i16, i16) {
-    ((b.0 as i16 - a.0 as i16), (b.1 as i16 - a.1 as i16))
-}
+    fn is_dead(&self) -> bool {
+        self.respawn_at.is_some()
+    }
+
+    fn update_flag(&mut self, status: FlagStatus) {
+        self.flags.retain(|f| f.team != status.team);
+        self.flags.push(status);
+        self.flags.sort_by_key(|f| f.team as u8);
+    }
+
+    fn update_cell(&mut self, cell: MapCell) {
+        match self
+            .visible_map
+            .iter_mut()
+            .find(|c| c.coords == cell.coords)
+        {
+            Some(visible) => visible.block = cell.block,
+            None => self.visible_map.push(cell),
+        }
+    }
+
+    fn player_label<'a>(&self, id: u32, name: &'a str) -> &'a str {
+        if id == self.id {
+            "YOU"
+        } else {
+            name
+        }
+    }
+
+    fn push_kill_feed(&mut self, kill: KillFeed) {
+        if self.kill_feed.len() == KILL_FEED_LEN {
+            self.kill_feed.pop_front();
+        }
+        self.kill_feed.push_back(kill);
+    }
+
+    fn push_chat_message(&mut self, message: ChatMessage) {
+        if self.chat_history.len() == CHAT_HISTORY_LEN {
+            self.chat_history.pop_front();
+        }
+        self.chat_history.push_back(message);
+    }
+
+    /// Whole seconds left until respawn, rounded up
+    fn respawn_countdown(&self) -> Option<u64> {
+        self.respawn_at.map(|at| {
+            at.saturating_duration_since(Instant::now())
+                .as_secs_f32()
+                .ceil() as u64
+        })
+    }
+
+    /// Whole seconds left in the current match phase, rounded up
+    fn match_countdown(&self) -> Option<u64> {
+        self.match_ends_at.map(|at| {
+            at.saturating_duration_since(Instant::now())
+                .as_secs_f32()
+                .ceil() as u64
+        })
+    }
+
+    fn status_line(&self) -> Option<&'static str> {
+        self.status
+            .filter(|&(_, until)| Instant::now() < until)
+            .map(|(reason, _)| reason.message())
+    }
+
+    fn can_act(&self) -> bool {
+        !self.is_dead() && self.match_phase != MatchPhase::PostMatch && self.reconnect_at.is_none()
+    }
 
-fn to_absolute((x, y): Coords, (padding_x, padding_y): (i16, i16)) -> Coords {
-    ((x as i16 + padding_x) as u16, (y as i16 + padding_y) as u16)
+    fn winner_label(&self, winner: Winner) -> String {
+        match winner {
+            Winner::Draw => "DRAW".to_string(),
+            Winner::Team(team) => format!("{} TEAM WINS", team.name()),
+            Winner::Player(id) => {
+                let name = self
+                    .match_results
+                    .iter()
+                    .flat_map(|r| &r.scores)
+                    .find(|s| s.id == id)
+                    .map_or("", |s| s.name.as_str());
+                format!("{} WINS", self.player_label(id, name))
+            }
+        }
+    }
 }
 
 fn draw_map(
     stdout: &Arc<Mutex<io::Stdout>>,
-    (terminal_height, terminal_width): (u16, u16),
+    (terminal_width, terminal_height): (u16, u16),
     client: &Arc<RwLock<Client>>,
 ) -> io::Result<()> {
-    let player_terminal_coords = (terminal_width / 2, terminal_height / 2);
+    let player_screen_cell = Position::new(terminal_height / 2, terminal_width / 2);
     let client = client.read().unwrap();
-    let padding = get_padding(client.coords, player_terminal_coords);
+    // the map is drawn around the player, whatever does not fit on the screen is left out
+    let to_screen = |position: Position| {
+        player_screen_cell.offset(
+            client.coords.vector_to(position),
+            terminal_height as usize,
+            terminal_width as usize,
+        )
+    };
 
     let mut stdout = stdout.lock().unwrap();
 
     // print visible_map
-    for (block, (x, y)) in client
+    for (block, cell) in client
         .visible_map
         .iter()
-        .map(|&MapCell { block, coords }| (block, to_absolute(coords, padding)))
+        .filter_map(|&MapCell { block, coords }| Some((block, to_screen(coords)?)))
     {
-        stdout.queue(MoveTo(y as u16, x as u16))?;
+        stdout.queue(MoveTo(cell.col, cell.row))?;
         stdout.queue(PrintStyledContent(BlockWrapper(block).into()))?;
     }
 
+    // print projectiles, players are drawn over them
+    for (projectile, cell) in client
+        .projectiles
+        .values()
+        .filter_map(|p| Some((p, to_screen(p.coords)?)))
+    {
+        stdout.queue(MoveTo(cell.col, cell.row))?;
+        stdout.queue(PrintStyledContent(projectile_symbol(projectile)))?;
+    }
+
+    // print other_players names above them
+    for (p, cell) in client
+        .other_players
+        .values()
+        .filter_map(|p| Some((p, to_screen(p.coords)?)))
+    {
+        let half_width = p.name.chars().count() as u16 / 2;
+        stdout.queue(MoveTo(
+            cell.col.saturating_sub(half_width),
+            cell.row.saturating_sub(1),
+        ))?;
+        stdout.queue(PrintStyledContent(
+            p.name.as_str().with(team_name_color(p.team)),
+        ))?;
+    }
+
     // print other_players
-    for (x, y) in client
+    for (team, cell) in client
         .other_players
         .values()
-        .map(|p| to_absolute(p.coords, padding))
+        .filter_map(|p| Some((p.team, to_screen(p.coords)?)))
     {
-        stdout.queue(MoveTo(y as u16, x as u16))?;
-        stdout.queue(PrintStyledContent('E'.red()))?;
+        stdout.queue(MoveTo(cell.col, cell.row))?;
+        stdout.queue(PrintStyledContent('E'.with(team_color(team))))?;
     }
 
     // print remove players
-    for (x, y) in client
+    for cell in client
         .players_outside
         .values()
-        .map(|p| to_absolute(p.coords, padding))
+        .filter_map(|p| to_screen(p.coords))
     {
-        stdout.queue(MoveTo(y as u16, x as u16))?;
+        stdout.queue(MoveTo(cell.col, cell.row))?;
         stdout.queue(PrintStyledContent('?'.yellow()))?;
     }
 
     // print player
-    stdout.queue(MoveTo(player_terminal_coords.1, player_terminal_coords.0))?;
+    stdout.queue(MoveTo(player_screen_cell.col, player_screen_cell.row))?;
     stdout.queue(PrintStyledContent(BlockWrapper(Block::Player).into()))?;
 
     Ok(())
@@ -345,13 +869,328 @@ fn draw_map(
 fn draw_metadata(stdout: &Arc<Mutex<io::Stdout>>, client: &Arc<RwLock<Client>>) -> io::Result<()> {
     let mut stdout = stdout.lock().unwrap();
     let client = client.read().unwrap();
-    let (x, y) = client.coords;
+    let Position { row, col } = client.coords;
     stdout.queue(MoveTo(0, 0))?;
-    stdout.queue(PrintStyledContent(format!("XY ({:2}:{:2})", x, y).green()))?;
+    stdout.queue(PrintStyledContent(client.name.as_str().blue()))?;
+    let latency = client
+        .latency
+        .map_or("--".to_string(), |latency| latency.as_millis().to_string());
+    stdout.queue(PrintStyledContent(
+        format!(" {latency}ms {}pkt/s", client.packet_rate).dark_grey(),
+    ))?;
     stdout.queue(MoveTo(0, 1))?;
+    stdout.queue(PrintStyledContent(
+        format!(
+            "XY ({:2}:{:2}) AIM {}",
+            row,
+            col,
+            direction_arrow(client.aim)
+        )
+        .green(),
+    ))?;
+    stdout.queue(MoveTo(0, 2))?;
     stdout.queue(PrintStyledContent(
         format!("HP ({:2}/{:2})", client.current_hp, client.max_hp).red(),
     ))?;
+    if client.inventory.armor > 0 {
+        stdout.queue(PrintStyledContent(
+            format!(" ARMOR {}", client.inventory.armor).cyan(),
+        ))?;
+    }
+    let weapon = &client.weapon;
+    let spec = weapon.kind.spec();
+    let reserve = match spec.max_reserve {
+        Some(_) => weapon.reserve.to_string(),
+        None => "∞".to_string(),
+    };
+    stdout.queue(MoveTo(0, 3))?;
+    stdout.queue(PrintStyledContent(
+        format!("{} {:2}/{}", spec.name, weapon.ammo, reserve).magenta(),
+    ))?;
+    if weapon.reloading {
+        stdout.queue(PrintStyledContent(" RELOADING".dark_grey()))?;
+    }
+    // owned weapon slots, empty ones as dashes
+    let slots = ('1'..='4')
+        .filter_map(|slot| WeaponKind::from_slot(slot).map(|kind| (slot, kind)))
+        .map(
+            |(slot, kind)| match client.inventory.weapons.contains(&kind) {
+                true => slot,
+                false => '-',
+            },
+        )
+        .collect::<String>();
+    stdout.queue(MoveTo(0, 4))?;
+    stdout.queue(PrintStyledContent(
+        format!("SLOTS {slots} MEDKITS {}", client.inventory.health_packs).magenta(),
+    ))?;
+    if let Some(respawn_in) = client.respawn_countdown() {
+        stdout.queue(MoveTo(0, 5))?;
+        stdout.queue(PrintStyledContent(
+            format!("RESPAWN IN {:2}s", respawn_in).yellow(),
+        ))?;
+    }
+    if let Some(status) = client.status_line() {
+        stdout.queue(MoveTo(0, 6))?;
+        stdout.queue(PrintStyledContent(status.to_uppercase().dark_yellow()))?;
+    }
+    if client.reconnect_at.is_some() {
+        let line = match &client.farewell {
+            Some(farewell) => format!("{} - RECONNECTING", farewell.reason.to_uppercase()),
+            None => "RECONNECTING".to_string(),
+        };
+        stdout.queue(MoveTo(0, 7))?;
+        stdout.queue(PrintStyledContent(line.yellow()))?;
+    }
+
+    Ok(())
+}
+
+fn countdown_digit(secs: u64) -> Option<&'static str> {
+    match secs {
+        1 => Some(ONE),
+        2 => Some(TWO),
+        3 => Some(THREE),
+        4 => Some(FOUR),
+        5 => Some(FIVE),
+        6 => Some(SIX),
+        7 => Some(SEVEN),
+        8 => Some(EIGHT),
+        9 => Some(NINE),
+        _ => None,
+    }
+}
+
+fn draw_centered_art(
+    stdout: &mut io::Stdout,
+    art: &str,
+    terminal_width: u16,
+    top: u16,
+) -> io::Result<()> {
+    // art constants start with a newline
+    for (i, line) in art.lines().skip(1).enumerate() {
+        let width = line.chars().count() as u16;
+        stdout.queue(MoveTo(
+            terminal_width.saturating_sub(width) / 2,
+            top + i as u16,
+        ))?;
+        stdout.queue(PrintStyledContent(line.red()))?;
+    }
+
+    Ok(())
+}
+
+fn draw_death_overlay(
+    stdout: &Arc<Mutex<io::Stdout>>,
+    (terminal_width, terminal_height): (u16, u16),
+    client: &Arc<RwLock<Client>>,
+) -> io::Result<()> {
+    let Some(respawn_in) = client.read().unwrap().respawn_countdown() else {
+        return Ok(());
+    };
+    let mut stdout = stdout.lock().unwrap();
+    let mid_terminal_height = terminal_height / 2;
+
+    draw_centered_art(
+        &mut stdout,
+        KILLED_YOU,
+        terminal_width,
+        mid_terminal_height.saturating_sub(6),
+    )?;
+    if let Some(digit) = countdown_digit(respawn_in) {
+        draw_centered_art(&mut stdout, digit, terminal_width, mid_terminal_height + 1)?;
+    }
+
+    Ok(())
+}
+
+fn draw_kill_feed(
+    stdout: &Arc<Mutex<io::Stdout>>,
+    (terminal_width, _): (u16, u16),
+    client: &Arc<RwLock<Client>>,
+) -> io::Result<()> {
+    let mut stdout = stdout.lock().unwrap();
+    let client = client.read().unwrap();
+
+    for (row, kill) in client.kill_feed.iter().rev().enumerate() {
+        let line = format!(
+            "{} killed {}",
+            client.player_label(kill.killer, &kill.killer_name),
+            client.player_label(kill.victim, &kill.victim_name)
+        );
+        let width = line.chars().count() as u16;
+        stdout.queue(MoveTo(terminal_width.saturating_sub(width), row as u16))?;
+        stdout.queue(PrintStyledContent(line.dark_yellow()))?;
+    }
+
+    Ok(())
+}
+
+fn flag_state_label(client: &Client, state: FlagState) -> &'static str {
+    match state {
+        FlagState::AtBase => "HOME",
+        FlagState::Carried(id) if id == client.id => "YOU HAVE IT",
+        FlagState::Carried(_) => "TAKEN",
+        FlagState::Dropped(_) => "DROPPED",
+    }
+}
+
+fn match_label(client: &Client) -> String {
+    let countdown = client.match_countdown().unwrap_or(0);
+    let clock = format!("{}:{:02}", countdown / 60, countdown % 60);
+
+    match client.match_phase {
+        MatchPhase::Lobby => format!(
+            "WAITING FOR PLAYERS {}/{}",
+            client.match_players.0, client.match_players.1
+        ),
+        MatchPhase::Warmup => format!("WARMUP {clock}"),
+        MatchPhase::Live => format!("LIVE {clock}"),
+        MatchPhase::PostMatch => format!("NEXT MATCH IN {clock}"),
+    }
+}
+
+/// Top center, the match phase with its countdown, the team scores and in capture the flag
+/// where the flags are
+fn draw_match_hud(
+    stdout: &Arc<Mutex<io::Stdout>>,
+    (terminal_width, _): (u16, u16),
+    client: &Arc<RwLock<Client>>,
+) -> io::Result<()> {
+    let client = client.read().unwrap();
+    let mut stdout = stdout.lock().unwrap();
+
+    let mut lines = vec![
+        (match_label(&client), Color::Yellow),
+        (client.mode.name().to_string(), Color::White),
+    ];
+    if let Some(team) = client.team {
+        lines.push((format!("YOU ARE {}", team.name()), team_color(Some(team))));
+    }
+    if client.mode.has_teams() {
+        lines.push((
+            format!(
+                "{} {} : {} {}",
+                Team::Red.name(),
+                client.team_scores.get(Team::Red),
+                client.team_scores.get(Team::Blue),
+                Team::Blue.name()
+            ),
+            Color::White,
+        ));
+    }
+    for flag in &client.flags {
+        lines.push((
+            format!(
+                "{} FLAG {}",
+                flag.team.name(),
+                flag_state_label(&client, flag.state)
+            ),
+            team_color(Some(flag.team)),
+        ));
+    }
+
+    for (row, (line, color)) in lines.into_iter().enumerate() {
+        let width = line.chars().count() as u16;
+        stdout.queue(MoveTo(terminal_width.saturating_sub(width) / 2, row as u16))?;
+        stdout.queue(PrintStyledContent(line.with(color)))?;
+    }
+
+    Ok(())
+}
+
+fn draw_scoreboard(
+    stdout: &Arc<Mutex<io::Stdout>>,
+    (terminal_width, terminal_height): (u16, u16),
+    client: &Arc<RwLock<Client>>,
+) -> io::Result<()> {
+    let client = client.read().unwrap();
+    // the final results stay up until the next match
+    let scores = match &client.match_results {
+        Some(results) => &results.scores,
+        None if client.show_scoreboard => &client.scoreboard,
+        None => return Ok(()),
+    };
+    let mut stdout = stdout.lock().unwrap();
+
+    let header = format!(
+        "{:<17}{:>6}{:>8}{:>8}{:>6}",
+        "PLAYER", "KILLS", "DEATHS", "STREAK", "BEST"
+    );
+    let width = header.chars().count() as u16;
+    let left = terminal_width.saturating_sub(width) / 2;
+    let top = (terminal_height / 2).saturating_sub(scores.len() as u16 / 2 + 1);
+
+    if let Some(results) = &client.match_results {
+        let banner = client.winner_label(results.winner);
+        let banner_width = banner.chars().count() as u16;
+        stdout.queue(MoveTo(
+            terminal_width.saturating_sub(banner_width) / 2,
+            top.saturating_sub(2),
+        ))?;
+        stdout.queue(PrintStyledContent(banner.yellow().on_black()))?;
+    }
+
+    stdout.queue(MoveTo(left, top))?;
+    stdout.queue(PrintStyledContent(header.black().on_grey()))?;
+    for (row, score) in scores.iter().enumerate() {
+        let line = format!(
+            "{:<17}{:>6}{:>8}{:>8}{:>6}",
+            score.name, score.kills, score.deaths, score.streak, score.best_streak
+        );
+        stdout.queue(MoveTo(left, top + 1 + row as u16))?;
+        if score.id == client.id {
+            stdout.queue(PrintStyledContent(line.blue().on_black()))?;
+        } else if client.mode.has_teams() {
+            stdout.queue(PrintStyledContent(
+                line.with(team_color(score.team)).on_black(),
+            ))?;
+        } else {
+            stdout.queue(PrintStyledContent(line.white().on_black()))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn channel_tag(channel: ChatChannel) -> &'static str {
+    match channel {
+        ChatChannel::Proximity => "NEAR",
+        ChatChannel::Global => "ALL",
+    }
+}
+
+fn draw_chat(
+    stdout: &Arc<Mutex<io::Stdout>>,
+    (_, terminal_height): (u16, u16),
+    client: &Arc<RwLock<Client>>,
+) -> io::Result<()> {
+    let mut stdout = stdout.lock().unwrap();
+    let client = client.read().unwrap();
+
+    let mut bottom = terminal_height;
+    if let Some(input) = &client.chat_input {
+        bottom = bottom.saturating_sub(1);
+        stdout.queue(MoveTo(0, bottom))?;
+        stdout.queue(PrintStyledContent(
+            format!("[{}]> {input}_", channel_tag(client.chat_channel)).white(),
+        ))?;
+    }
+
+    let top = bottom.saturating_sub(client.chat_history.len() as u16);
+    for (row, message) in client.chat_history.iter().enumerate() {
+        let line = format!(
+            "[{}] {}: {}",
+            channel_tag(message.channel),
+            client.player_label(message.from, &message.from_name),
+            message.text
+        );
+        stdout.queue(MoveTo(0, top + row as u16))?;
+        match message.channel {
+            ChatChannel::Proximity => stdout.queue(PrintStyledContent(line.cyan()))?,
+            ChatChannel::Global => stdout.queue(PrintStyledContent(line.magenta()))?,
+        };
+    }
 
     Ok(())
 }
@@ -366,18 +1205,26 @@ fn rerender(
         stdout.queue(Clear(ClearType::All))?;
     }
     draw_map(stdout, terminal_dimensions, client)?;
+    draw_death_overlay(stdout, terminal_dimensions, client)?;
     draw_metadata(stdout, client)?;
+    draw_kill_feed(stdout, terminal_dimensions, client)?;
+    draw_match_hud(stdout, terminal_dimensions, client)?;
+    draw_chat(stdout, terminal_dimensions, client)?;
+    draw_scoreboard(stdout, terminal_dimensions, client)?;
 
     Ok(())
 }
 
 enum CommandEnum {
-    MoveTo(i8, i8),
+    // offset from the cell the player is drawn at
+    MoveTo(Vector),
     PrintStyledContent(StyledContent<char>),
     ReRender,
 }
 
 fn main() -> io::Result<()> {
+    let name_arg = name_from_args(std::env::args().skip(1));
+
     terminal::enable_raw_mode()?;
     let mut terminal_dimensions = terminal::size()?;
 
@@ -386,10 +1233,26 @@ fn main() -> io::Result<()> {
 
     print_logo_scene(&stdout, terminal_dimensions)?;
 
-    let mut buf = [0; 512];
+    let name = match name_arg {
+        Some(name) => name,
+        None => prompt_name(&stdout, terminal_dimensions)?,
+    };
+
+    let mut buf = [0; 2048];
     let client = Arc::new(RwLock::new(Client::default()));
     {
-        client.write().unwrap().connect(LOCAL_HOST, PORT);
+        let mut client = client.write().unwrap();
+        // joins with the name once the server can be reached
+        client.name = name.clone();
+        match client.connect(LOCAL_HOST, PORT) {
+            Ok(()) => client
+                .send_join(&mut buf, name)
+                .map_err(|_| io::Error::other("send join"))?,
+            Err(err) => {
+                log_error!("Could not connect to {LOCAL_HOST}:{PORT}, {err}");
+                client.connection_lost();
+            }
+        }
     }
 
     let (animation_sender, animation_receiver) = channel::<Vec<CommandEnum>>();
@@ -398,26 +1261,30 @@ fn main() -> io::Result<()> {
     thread::spawn(move || {
         let stdout = Arc::clone(&stdout_animation_recv);
         let client = Arc::clone(&client_animation_recv);
+        // content meant for a cell off the screen is not printed
+        let mut on_screen = true;
 
         loop {
             match animation_receiver.recv() {
                 Ok(events) => {
                     for event in events {
                         match event {
-                            CommandEnum::MoveTo(y, x) => {
+                            CommandEnum::MoveTo(offset) => {
                                 let (w, h) = terminal::size().unwrap();
-                                // TODO god bless me refactor this pls my brain hurts a lot
-                                let (y, x) = (
-                                    ((w / 2) as i16 + y as i16) as u16,
-                                    ((h / 2) as i16 + x as i16) as u16,
-                                );
-                                let mut stdout = stdout.lock().unwrap();
-                                stdout.queue(MoveTo(y, x)).unwrap();
+                                let player_screen_cell = Position::new(h / 2, w / 2);
+                                let cell =
+                                    player_screen_cell.offset(offset, h as usize, w as usize);
+                                on_screen = cell.is_some();
+                                if let Some(cell) = cell {
+                                    let mut stdout = stdout.lock().unwrap();
+                                    stdout.queue(MoveTo(cell.col, cell.row)).unwrap();
+                                }
                             }
-                            CommandEnum::PrintStyledContent(symbol) => {
+                            CommandEnum::PrintStyledContent(symbol) if on_screen => {
                                 let mut stdout = stdout.lock().unwrap();
                                 stdout.queue(PrintStyledContent(symbol)).unwrap();
                             }
+                            CommandEnum::PrintStyledContent(_) => {}
                             CommandEnum::ReRender => {
                                 rerender(&stdout, &client, terminal::size().unwrap()).unwrap();
                             }
@@ -437,15 +1304,10 @@ fn main() -> io::Result<()> {
         }
     });
 
+    let mut shown_hud = (None, None, false, false, None, 0);
     loop {
         while poll(Duration::ZERO)? {
-            handle_io_read(
-                &stdout,
-                &client,
-                &mut terminal_dimensions,
-                &mut buf,
-                &animation_sender,
-            )?;
+            handle_io_read(&stdout, &client, &mut terminal_dimensions, &mut buf)?;
         }
 
         // FIXME i hate this approach dont need to take and return the stream + wont need to drop
@@ -455,9 +1317,64 @@ fn main() -> io::Result<()> {
             client.stream.take()
         };
         if let Some(mut s) = stream {
-            handle_tcp_read(&mut s, &stdout, &client, terminal_dimensions, &mut buf)?;
+            handle_tcp_read(
+                &mut s,
+                &stdout,
+                &client,
+                terminal_dimensions,
+                &mut buf,
+                &animation_sender,
+            )?;
             let mut client = client.write().unwrap();
-            client.stream = Some(s);
+            // the read may have found the connection gone
+            if client.reconnect_at.is_none() {
+                client.stream = Some(s);
+            }
+        }
+
+        client
+            .write()
+            .unwrap()
+            .heartbeat(&mut buf)
+            .map_err(|_| io::Error::other("send ping"))?;
+
+        let gave_up = {
+            let mut client = client.write().unwrap();
+            let gave_up = client.try_reconnect(&mut buf).is_err();
+            gave_up.then(|| client.disconnect_reason())
+        };
+        if let Some(reason) = gave_up {
+            terminal::disable_raw_mode()?;
+            stdout.lock().unwrap().queue(Clear(ClearType::All))?;
+            log_info!("{reason}");
+            exit(0);
+        }
+
+        {
+            let mut client = client.write().unwrap();
+            if client.show_scoreboard && client.scoreboard_stale {
+                client.scoreboard_stale = false;
+                client
+                    .send_scoreboard_request(&mut buf)
+                    .map_err(|_| io::Error::other("send scoreboard request"))?;
+            }
+        }
+
+        // the status line goes away on its own as well
+        let hud = {
+            let client = client.read().unwrap();
+            (
+                client.respawn_countdown(),
+                client.match_countdown(),
+                client.status_line().is_some(),
+                client.reconnect_at.is_some(),
+                client.latency.map(|latency| latency.as_millis()),
+                client.packet_rate,
+            )
+        };
+        if hud != shown_hud {
+            shown_hud = hud;
+            rerender(&stdout, &client, terminal_dimensions)?;
         }
 
         let mut stdout = stdout.lock().unwrap();
@@ -466,7 +1383,7 @@ fn main() -> io::Result<()> {
         thread::sleep(Duration::from_millis(33));
 
         if let Ok(mut client) = client.write() {
-            if client.quit == true {
+            if client.quit {
                 if let Some(s) = client.stream.take() {
                     drop(s);
                 }
@@ -475,14 +1392,15 @@ fn main() -> io::Result<()> {
         }
     }
 
-    draw_death_scene(&stdout, terminal_dimensions)?;
+    terminal::disable_raw_mode()?;
+    stdout.lock().unwrap().queue(Clear(ClearType::All))?;
 
     Ok(())
 }
 
 fn print_logo_scene(
     stdout: &Arc<Mutex<io::Stdout>>,
-    (terminal_width, terminal_height): Coords,
+    (terminal_width, terminal_height): (u16, u16),
 ) -> io::Result<()> {
     let mut stdout = stdout.lock().unwrap();
 
@@ -503,7 +1421,7 @@ fn print_logo_scene(
             let row_slice = &row[max(0, min(row_width as i16, -w)) as usize
                 ..min((terminal_width - max(w, 0) as u16) as usize, row.len())];
             stdout.queue(PrintStyledContent(
-                row_slice.into_iter().collect::<String>().red(),
+                row_slice.iter().collect::<String>().red(),
             ))?;
         }
         stdout.flush()?;
@@ -513,41 +1431,57 @@ fn print_logo_scene(
     Ok(())
 }
 
-fn draw_death_scene(
-    stdout: &Arc<Mutex<io::Stdout>>,
-    (terminal_width, terminal_height): Coords,
-) -> io::Result<()> {
-    let mut stdout = stdout.lock().unwrap();
+fn name_from_args(mut args: impl Iterator<Item = String>) -> Option<String> {
+    while let Some(arg) = args.next() {
+        if arg == "--name" {
+            return args.next();
+        }
+    }
 
-    stdout.queue(Clear(ClearType::All))?;
+    None
+}
 
-    let logo_as_2d_vec = KILLED_YOU
-        .lines()
-        .map(|line| line.chars().collect::<Vec<_>>())
-        .collect::<Vec<_>>();
-    let height = logo_as_2d_vec.len() as i16;
-    let max_width = logo_as_2d_vec.iter().map(|row| row.len()).max().unwrap() as i16;
-    let mid_terminal_height = terminal_height / 2;
+fn prompt_name(
+    stdout: &Arc<Mutex<io::Stdout>>,
+    (terminal_width, terminal_height): (u16, u16),
+) -> io::Result<String> {
+    let mut name = String::new();
 
-    for w in (-max_width..terminal_width as i16).rev() {
-        for (h, i) in (-height / 2..height / 2).zip(0..height) {
-            let h = (mid_terminal_height as i16 + h) as u16;
-            stdout.queue(MoveTo(max(w, 0) as u16, h))?;
-            let row = &logo_as_2d_vec[i as usize];
-            let row_width = row.len();
-            let row_slice = &row[max(0, min(row_width as i16, -w)) as usize
-                ..min((terminal_width - max(w, 0) as u16) as usize, row.len())];
-            stdout.queue(PrintStyledContent(
-                row_slice.into_iter().collect::<String>().red(),
+    loop {
+        {
+            let mut stdout = stdout.lock().unwrap();
+            let line = format!("NAME: {name}_");
+            stdout.queue(Clear(ClearType::All))?;
+            stdout.queue(MoveTo(
+                terminal_width.saturating_sub(line.len() as u16) / 2,
+                terminal_height / 2,
             ))?;
+            stdout.queue(PrintStyledContent(line.red()))?;
+            stdout.flush()?;
         }
-        stdout.flush()?;
-        thread::sleep(Duration::from_millis(33));
-    }
 
-    thread::sleep(Duration::from_millis(333));
-
-    Ok(())
+        if let Event::Key(event) = read()? {
+            match event.code {
+                KeyCode::Char('c') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                    let mut stdout = stdout.lock().unwrap();
+                    terminal::disable_raw_mode()?;
+                    stdout.queue(Clear(ClearType::All))?;
+                    exit(0);
+                }
+                KeyCode::Enter if !name.is_empty() => return Ok(name),
+                KeyCode::Backspace => {
+                    name.pop();
+                }
+                KeyCode::Char(c)
+                    if name.len() < MAX_NAME_LEN
+                        && (c.is_ascii_alphanumeric() || c == '_' || c == '-') =>
+                {
+                    name.push(c)
+                }
+                _ => {}
+            }
+        }
+    }
 }
 
 fn configure_stdout(stdout: &Arc<Mutex<io::Stdout>>) -> io::Result<()> {
@@ -564,7 +1498,6 @@ fn handle_io_read(
     client: &Arc<RwLock<Client>>,
     terminal_dimensions: &mut (u16, u16),
     buf: &mut [u8],
-    animation_sender: &Sender<Vec<CommandEnum>>,
 ) -> io::Result<()> {
     match read()? {
         Event::Resize(w, h) => {
@@ -572,96 +1505,87 @@ fn handle_io_read(
             terminal_dimensions.1 = h;
         }
         Event::Key(event) => {
+            if event.code == KeyCode::Char('c') && event.modifiers.contains(KeyModifiers::CONTROL) {
+                let mut stdout = stdout.lock().unwrap();
+                terminal::disable_raw_mode()?;
+                stdout.queue(Clear(ClearType::All))?;
+                exit(0);
+            }
+
+            if client.read().unwrap().chat_input.is_some() {
+                handle_chat_key(client, buf, event.code)?;
+                rerender(stdout, client, *terminal_dimensions)?;
+                return Ok(());
+            }
+
+            if event.code == KeyCode::Enter {
+                client.write().unwrap().chat_input = Some(String::new());
+                rerender(stdout, client, *terminal_dimensions)?;
+                return Ok(());
+            }
+
+            if event.code == KeyCode::Tab {
+                {
+                    let mut client = client.write().unwrap();
+                    client.show_scoreboard = !client.show_scoreboard;
+                    client.scoreboard_stale = client.show_scoreboard;
+                }
+                rerender(stdout, client, *terminal_dimensions)?;
+            }
+
+            if let Some(aim) = aim_key(event.code) {
+                client.write().unwrap().aim = aim;
+                rerender(stdout, client, *terminal_dimensions)?;
+                return Ok(());
+            }
+
             if let KeyCode::Char(c) = event.code {
-                if c == 'c' && event.modifiers.contains(KeyModifiers::CONTROL) {
-                    let mut stdout = stdout.lock().unwrap();
-                    terminal::disable_raw_mode()?;
-                    stdout.queue(Clear(ClearType::All))?;
-                    exit(0);
+                // spectating until respawn or the next match
+                if !client.read().unwrap().can_act() {
+                    return Ok(());
                 }
 
                 match c {
-                    'w' | 'k' | 'a' | 'h' | 's' | 'j' | 'd' | 'l' => {
-                        let mut client = client.write().unwrap();
+                    c if Direction::try_from(c).is_ok() => {
                         client
+                            .write()
+                            .unwrap()
                             .send_move(buf, c)
-                            .map_err(|_| io::Error::new(io::ErrorKind::Other, "send move"))?;
-
-                        client.shooting_angle = match c {
-                            'w' | 'k' => Direction::Up,
-                            's' | 'j' => Direction::Down,
-                            'a' | 'h' => Direction::Left,
-                            'd' | 'l' => Direction::Right,
-                            _ => unreachable!(),
+                            .map_err(|_| io::Error::other("send move"))?;
+                        // the predicted step shows before the server answers
+                        rerender(stdout, client, *terminal_dimensions)?;
+                    }
+                    '1'..='4' => {
+                        let mut client = client.write().unwrap();
+                        if let Some(kind) = WeaponKind::from_slot(c) {
+                            client
+                                .send_switch_weapon(buf, kind)
+                                .map_err(|_| io::Error::other("send switch weapon"))?;
+                        }
+                    }
+                    'r' => {
+                        let mut client = client.write().unwrap();
+                        client
+                            .send_reload(buf)
+                            .map_err(|_| io::Error::other("send reload"))?;
+                    }
+                    'f' => {
+                        let mut client = client.write().unwrap();
+                        if client.inventory.health_packs > 0 {
+                            client
+                                .send_use_health_pack(buf)
+                                .map_err(|_| io::Error::other("send use health pack"))?;
                         }
                     }
                     ' ' => {
                         let mut client = client.write().unwrap();
+                        if !client.weapon.can_fire() {
+                            return Ok(());
+                        }
                         client
                             .send_shoot(buf)
-                            .map_err(|_| io::Error::new(io::ErrorKind::Other, "send shoot"))?;
-
-                        let shooting_angle = client.shooting_angle;
-                        let range = client.weapon.range as i8;
-                        let (absolute_x, absolute_y) = (0, 0);
-                        let animation_sender = animation_sender.clone();
-                        thread::spawn(move || {
-                            let point_from = match shooting_angle {
-                                Direction::Up => (absolute_x - 1, absolute_y),
-                                Direction::Down => (absolute_x + 1, absolute_y),
-                                Direction::Left => (absolute_x, absolute_y - 1),
-                                Direction::Right => (absolute_x, absolute_y + 1),
-                            };
-
-                            let point_to = match shooting_angle {
-                                Direction::Up => (point_from.0 - range, point_from.1),
-                                Direction::Down => (point_from.0 + range, point_from.1),
-                                Direction::Left => (point_from.0, point_from.1 - range),
-                                Direction::Right => (point_from.0, point_from.1 + range),
-                            };
-
-                            let symbol = match shooting_angle {
-                                Direction::Up | Direction::Down => '║',
-                                Direction::Left | Direction::Right => '═',
-                            };
-
-                            match shooting_angle {
-                                Direction::Right | Direction::Down => {
-                                    for x in point_from.0..=point_to.0 {
-                                        for y in point_from.1..=point_to.1 {
-                                            animation_sender
-                                                .send(vec![
-                                                    CommandEnum::MoveTo(y, x),
-                                                    CommandEnum::PrintStyledContent(
-                                                        symbol.dark_red(),
-                                                    ),
-                                                ])
-                                                .unwrap();
-
-                                            thread::sleep(Duration::from_millis(33));
-                                        }
-                                    }
-                                    animation_sender.send(vec![CommandEnum::ReRender]).unwrap();
-                                }
-                                Direction::Up | Direction::Left => {
-                                    for x in (point_to.0..=point_from.0).rev() {
-                                        for y in (point_to.1..=point_from.1).rev() {
-                                            animation_sender
-                                                .send(vec![
-                                                    CommandEnum::MoveTo(y, x),
-                                                    CommandEnum::PrintStyledContent(
-                                                        symbol.dark_red(),
-                                                    ),
-                                                ])
-                                                .unwrap();
-
-                                            thread::sleep(Duration::from_millis(33));
-                                        }
-                                    }
-                                    animation_sender.send(vec![CommandEnum::ReRender]).unwrap();
-                                }
-                            }
-                        });
+                            .map_err(|_| io::Error::other("send shoot"))?;
+                        client.weapon.fired();
                     }
                     _ => {}
                 }
@@ -673,36 +1597,187 @@ fn handle_io_read(
     Ok(())
 }
 
+/// Shift with a movement key or an arrow key turns the aim without moving
+fn aim_key(code: KeyCode) -> Option<Direction> {
+    match code {
+        KeyCode::Up => Some(Direction::Up),
+        KeyCode::Right => Some(Direction::Right),
+        KeyCode::Down => Some(Direction::Down),
+        KeyCode::Left => Some(Direction::Left),
+        KeyCode::Char(c) if c.is_ascii_uppercase() => {
+            Direction::try_from(c.to_ascii_lowercase()).ok()
+        }
+        _ => None,
+    }
+}
+
+fn direction_arrow(direction: Direction) -> char {
+    match direction {
+        Direction::Up => '↑',
+        Direction::UpRight => '↗',
+        Direction::Right => '→',
+        Direction::DownRight => '↘',
+        Direction::Down => '↓',
+        Direction::DownLeft => '↙',
+        Direction::Left => '←',
+        Direction::UpLeft => '↖',
+    }
+}
+
+fn trail_symbol(direction: Direction) -> char {
+    match direction {
+        Direction::Up | Direction::Down => '║',
+        Direction::Left | Direction::Right => '═',
+        Direction::UpRight | Direction::DownLeft => '╱',
+        Direction::DownRight | Direction::UpLeft => '╲',
+    }
+}
+
+fn projectile_symbol(projectile: &Projectile) -> StyledContent<char> {
+    if projectile.kind.spec().blast_radius > 0 {
+        return 'o'.dark_red();
+    }
+
+    trail_symbol(projectile.direction).dark_red()
+}
+
+// Offsets are relative to the player, the animation thread centers them on the screen
+fn animate_shot(animation_sender: &Sender<Vec<CommandEnum>>, origin: Vector, shot: ShotFired) {
+    let delta = shot.direction.delta();
+    let range = shot.range as i16;
+    let mut commands = (0..range - 1)
+        .flat_map(|distance| {
+            [
+                CommandEnum::MoveTo(origin + delta * distance),
+                CommandEnum::PrintStyledContent(trail_symbol(shot.direction).dark_grey()),
+            ]
+        })
+        .collect::<Vec<_>>();
+    // the cell it stopped on lights up red when somebody was hit there
+    let flash = match shot.hit {
+        Some(_) => '*'.red(),
+        None => '*'.dark_grey(),
+    };
+    commands.push(CommandEnum::MoveTo(origin + delta * (range - 1)));
+    commands.push(CommandEnum::PrintStyledContent(flash));
+    animation_sender.send(commands).unwrap();
+
+    thread::sleep(Duration::from_millis(150));
+
+    animation_sender.send(vec![CommandEnum::ReRender]).unwrap();
+}
+
+// The center is relative to the player, the animation thread centers it on the screen
+fn animate_impact(animation_sender: &Sender<Vec<CommandEnum>>, center: Vector, radius: u8) {
+    let radius = radius as i16;
+    let commands = (-radius..=radius)
+        .flat_map(|rows| (-radius..=radius).map(move |cols| Vector::new(rows, cols)))
+        .filter(|offset| offset.length_squared() <= (radius as i32).pow(2))
+        .flat_map(|offset| {
+            [
+                CommandEnum::MoveTo(center + offset),
+                CommandEnum::PrintStyledContent('*'.red()),
+            ]
+        })
+        .collect();
+    animation_sender.send(commands).unwrap();
+
+    thread::sleep(Duration::from_millis(150));
+
+    animation_sender.send(vec![CommandEnum::ReRender]).unwrap();
+}
+
+fn handle_chat_key(client: &Arc<RwLock<Client>>, buf: &mut [u8], code: KeyCode) -> io::Result<()> {
+    let mut client = client.write().unwrap();
+
+    match code {
+        KeyCode::Esc => client.chat_input = None,
+        KeyCode::Tab => {
+            client.chat_channel = match client.chat_channel {
+                ChatChannel::Proximity => ChatChannel::Global,
+                ChatChannel::Global => ChatChannel::Proximity,
+            }
+        }
+        KeyCode::Enter => {
+            if let Some(text) = client
+                .chat_input
+                .take()
+                .filter(|text| !text.trim().is_empty())
+            {
+                // `/admin <password> <command>` goes to the admin console instead
+                match text
+                    .strip_prefix("/admin ")
+                    .map(|rest| rest.trim().split_once(' '))
+                {
+                    Some(Some((password, command))) => client
+                        .send_admin(buf, password.to_string(), command.to_string())
+                        .map_err(|_| io::Error::other("send admin"))?,
+                    // never sent as chat, it may hold the password
+                    Some(None) => client.push_chat_message(ChatMessage {
+                        from: SERVER_ID,
+                        from_name: "admin".to_string(),
+                        channel: ChatChannel::Global,
+                        text: "Usage: /admin <password> <command>".to_string(),
+                    }),
+                    None => client
+                        .send_chat(buf, text)
+                        .map_err(|_| io::Error::other("send chat"))?,
+                }
+            }
+        }
+        KeyCode::Backspace => {
+            if let Some(input) = client.chat_input.as_mut() {
+                input.pop();
+            }
+        }
+        KeyCode::Char(c) => {
+            if let Some(input) = client.chat_input.as_mut() {
+                if input.chars().count() < MAX_CHAT_LEN {
+                    input.push(c);
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
 fn handle_tcp_read(
     s: &mut TcpStream,
     stdout: &Arc<Mutex<io::Stdout>>,
     client: &Arc<RwLock<Client>>,
     terminal_dimensions: (u16, u16),
     buf: &mut [u8],
+    animation_sender: &Sender<Vec<CommandEnum>>,
 ) -> io::Result<()> {
     match s.read(buf) {
-        Ok(0) => {
-            let mut client = client.write().unwrap();
-            client.stream = None;
-            terminal::disable_raw_mode()?;
-            let mut stdout = stdout.lock().unwrap();
-            stdout.queue(Clear(ClearType::All))?;
-            log_info!("Server closed the connection");
-            exit(0);
-        }
+        Ok(0) => client.write().unwrap().connection_lost(),
         Ok(n) => {
-            if let Ok((packet, _)) = Packet::deserialize(&buf[..n]) {
-                handle_packet(packet, stdout, &client, terminal_dimensions)?;
-            } else {
-                log_error!("Failed to deserialize server message");
+            // several packets can arrive in a single read
+            let mut offset = 0;
+            while offset < n {
+                match Packet::deserialize(&buf[offset..n]) {
+                    Ok((packet, size)) => {
+                        offset += size;
+                        handle_packet(
+                            packet,
+                            stdout,
+                            client,
+                            terminal_dimensions,
+                            animation_sender,
+                        )?;
+                    }
+                    Err(_) => {
+                        log_error!("Failed to deserialize server message");
+                        break;
+                    }
+                }
             }
         }
         Err(err) => {
             if err.kind() != ErrorKind::WouldBlock {
-                let mut client = client.write().unwrap();
-                client.stream = None;
-                log_error!("Connection error: {}", err);
-                exit(0);
+                client.write().unwrap().connection_lost();
             }
         }
     }
@@ -715,46 +1790,184 @@ fn handle_packet(
     stdout: &Arc<Mutex<io::Stdout>>,
     client: &Arc<RwLock<Client>>,
     terminal_dimensions: (u16, u16),
+    animation_sender: &Sender<Vec<CommandEnum>>,
 ) -> io::Result<()> {
     {
         let mut client = client.write().unwrap();
+        client.packets_received += 1;
         match packet {
             Packet::Server(s) => match s {
                 ServerPacket::NewClientCoordsVisibleMap(nc) => {
                     client.id = nc.id;
+                    client.name = nc.name;
                     client.coords = nc.coords;
-                    client.max_hp = nc.hp;
+                    client.max_hp = nc.max_hp;
                     client.current_hp = nc.hp;
                     client.radius = nc.radius;
-                    client.weapon.range = nc.weapon_range;
-                    client.visible_map =
-                        nc.visible_coords.into_iter().map(|mc| mc.into()).collect();
-                    client.other_players = nc
-                        .players
-                        .into_iter()
-                        .map(|p| (p.id, Player { coords: p.coords }))
-                        .collect();
+                    client.weapon.update(nc.weapon);
+                    client.visible_map = nc.visible_coords.into_iter().collect();
+                    client.other_players = nc.players.into_iter().map(player_entry).collect();
+                    client.players_outside.clear();
+                    client.projectiles.clear();
+                    // also sent on resuming, the server tells again if we are dead
+                    client.pending_moves.clear();
+                    client.respawn_at = None;
+                    client.session = Some(nc.token);
+                    client.farewell = None;
+                    client.reconnect_attempts = 0;
                 }
                 ServerPacket::NewCoords(nc) => {
+                    // everything the server sees is worked out from the confirmed position
                     client.coords = nc.center;
                     client.remove_non_visible();
-                    client
-                        .visible_map
-                        .append(&mut nc.coords.into_iter().map(|mc| mc.into()).collect());
-                    client.update_other_player_coords_after_move(&nc.players);
+                    for cell in nc.coords {
+                        client.update_cell(cell);
+                    }
+                    client.update_other_player_coords_after_move(nc.players);
+                    client.reconcile(nc.center, nc.ack);
                 }
-                ServerPacket::OtherPlayerMoved(OtherPlayerMoved { id, coords }) => {
-                    client.update_other_player_coords_after_other_player_move(id, coords);
+                ServerPacket::OtherPlayerMoved(OtherPlayerMoved {
+                    id,
+                    coords,
+                    name,
+                    team,
+                }) => {
+                    client
+                        .update_other_player_coords_after_other_player_move(id, coords, name, team);
                 }
                 ServerPacket::OtherPlayerMovedOutsideRadius(id)
                 | ServerPacket::PlayerDisconnected(id) => {
                     client.remove_player(id);
                 }
                 ServerPacket::PlayerWasShot(damage, _direction) => {
-                    client.current_hp = client.current_hp.checked_sub(damage).unwrap_or(0);
+                    client.current_hp = client.current_hp.saturating_sub(damage);
+                }
+                ServerPacket::PlayerDied(_by_id, respawn_in) => {
+                    client.current_hp = 0;
+                    client.respawn_at =
+                        Some(Instant::now() + Duration::from_secs(respawn_in as u64));
+                }
+                ServerPacket::OtherPlayerDied(id) => {
+                    client.remove_player(id);
+                }
+                ServerPacket::KillFeed(kill) => {
+                    client.push_kill_feed(kill);
+                    client.scoreboard_stale = true;
+                }
+                ServerPacket::Scoreboard(scoreboard) => {
+                    client.scoreboard = scoreboard.scores;
+                }
+                ServerPacket::ChatMessage(message) => {
+                    client.push_chat_message(message);
+                }
+                ServerPacket::WeaponState(state) => {
+                    client.weapon.update(state);
+                }
+                ServerPacket::MapCellChanged(cell) => {
+                    client.update_cell(cell);
+                }
+                ServerPacket::Inventory(inventory) => {
+                    client.inventory = inventory;
+                }
+                ServerPacket::GameInfo(info) => {
+                    client.mode = info.mode;
+                    client.team = info.team;
+                }
+                ServerPacket::TeamScores(scores) => {
+                    client.team_scores = scores;
+                }
+                ServerPacket::FlagStatus(status) => {
+                    client.update_flag(status);
+                }
+                ServerPacket::MatchState(state) => {
+                    client.match_phase = state.phase;
+                    client.match_ends_at = (state.seconds_left > 0)
+                        .then(|| Instant::now() + Duration::from_secs(state.seconds_left as u64));
+                    client.match_players = (state.players, state.min_players);
+                    if state.phase != MatchPhase::PostMatch {
+                        client.match_results = None;
+                    }
+                }
+                ServerPacket::MatchResults(results) => {
+                    client.team_scores = results.team_scores;
+                    client.match_results = Some(results);
+                }
+                ServerPacket::Healed(hp) => {
+                    client.current_hp = min(client.max_hp, client.current_hp.saturating_add(hp));
+                }
+                ServerPacket::Respawned(r) => {
+                    client.respawn_at = None;
+                    client.coords = r.coords;
+                    client.pending_moves.clear();
+                    client.max_hp = r.hp;
+                    client.current_hp = r.hp;
+                    client.visible_map = r.visible_coords;
+                    client.players_outside.clear();
+                    client.other_players = r.players.into_iter().map(player_entry).collect();
+                    client.projectiles.clear();
+                }
+                ServerPacket::ProjectileSpawned(projectile)
+                | ServerPacket::ProjectileMoved(projectile) => {
+                    client.update_projectile(projectile);
+                }
+                ServerPacket::ProjectileHit(id, coords) => {
+                    // the cell itself flashes with the shot, explosions light up around it
+                    let radius = client
+                        .projectiles
+                        .remove(&id)
+                        .map_or(0, |p| p.kind.spec().blast_radius);
+                    if radius > 0 {
+                        let center = client.coords.vector_to(coords);
+                        let animation_sender = animation_sender.clone();
+                        thread::spawn(move || animate_impact(&animation_sender, center, radius));
+                    }
+                }
+                ServerPacket::ActionRejected(rejected) => {
+                    client.status = Some((rejected.reason_code, Instant::now() + STATUS_LINE_TIME));
+                }
+                ServerPacket::Pong(nonce, timestamp) => {
+                    if nonce == client.ping_nonce {
+                        let rtt = utils::timestamp_millis().saturating_sub(timestamp);
+                        client.latency = Some(Duration::from_millis(rtt));
+                    }
+                }
+                // the connection is closed right after, which is when this is acted on
+                ServerPacket::ServerShutdown(shutdown) => {
+                    client.farewell = Some(shutdown);
+                }
+                ServerPacket::Kicked(reason) => {
+                    client.farewell = Some(ServerShutdown {
+                        reason: format!("Kicked: {reason}"),
+                        reconnect_in: None,
+                    });
+                }
+                ServerPacket::ServerFull => {
+                    client.farewell = Some(ServerShutdown {
+                        reason: "Server is full".to_string(),
+                        reconnect_in: None,
+                    });
+                }
+                ServerPacket::AdminReply(text) => {
+                    client.push_chat_message(ChatMessage {
+                        from: SERVER_ID,
+                        from_name: "admin".to_string(),
+                        channel: ChatChannel::Global,
+                        text,
+                    });
+                }
+                ServerPacket::HpChanged(hp) => {
+                    client.current_hp = hp;
+                }
+                ServerPacket::Ping(nonce) => {
+                    let mut buf = [0; 16];
+                    client
+                        .send_pong(&mut buf, nonce)
+                        .map_err(|_| io::Error::other("send pong"))?;
                 }
-                ServerPacket::PlayerDied(_id) => {
-                    client.quit = true;
+                ServerPacket::ShotFired(shot) => {
+                    let origin = client.coords.vector_to(shot.origin);
+                    let animation_sender = animation_sender.clone();
+                    thread::spawn(move || animate_shot(&animation_sender, origin, shot));
                 }
             },
             _ => panic!("Server cannot send client packets"),
@@ -0,0 +1,74 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use game_core::{
+    pathfinding::{self, FlowField},
+    types::{Block, Map, Position},
+};
+
+const SIZE: usize = 50;
+
+fn open_map() -> Map {
+    Map {
+        height: SIZE,
+        width: SIZE,
+        coords: vec![vec![Block::Grass; SIZE]; SIZE],
+    }
+}
+
+/// Vertical walls every fourth column with the gap alternating between the top and the bottom,
+/// so the only way across snakes through the whole map
+fn maze_map() -> Map {
+    let mut map = open_map();
+    for (n, col) in (2..SIZE - 1).step_by(4).enumerate() {
+        let gap = if n % 2 == 0 { SIZE - 1 } else { 0 };
+        for (row, cells) in map.coords.iter_mut().enumerate() {
+            if row != gap {
+                cells[col] = Block::WallVertical;
+            }
+        }
+    }
+
+    map
+}
+
+fn astar(c: &mut Criterion) {
+    let (from, to) = (
+        Position::new(0, 0),
+        Position::new(SIZE as u16 - 1, SIZE as u16 - 1),
+    );
+    let open = open_map();
+    let maze = maze_map();
+
+    c.bench_function("astar open 50x50", |b| {
+        b.iter(|| pathfinding::astar(black_box(&open), from, to))
+    });
+    c.bench_function("astar maze 50x50", |b| {
+        b.iter(|| pathfinding::astar(black_box(&maze), from, to))
+    });
+}
+
+fn flow_field(c: &mut Criterion) {
+    let goal = [Position::new(SIZE as u16 / 2, SIZE as u16 / 2)];
+    let open = open_map();
+    let maze = maze_map();
+
+    c.bench_function("flow field open 50x50", |b| {
+        b.iter(|| FlowField::new(black_box(&open), &goal))
+    });
+    c.bench_function("flow field maze 50x50", |b| {
+        b.iter(|| FlowField::new(black_box(&maze), &goal))
+    });
+
+    let field = FlowField::new(&maze, &goal);
+    c.bench_function("flow field walk maze 50x50", |b| {
+        b.iter(|| {
+            let mut cell = Position::new(0, 0);
+            while let Some(direction) = field.direction(black_box(cell)) {
+                cell = cell.neighbour(direction, SIZE, SIZE).unwrap();
+            }
+            cell
+        })
+    });
+}
+
+criterion_group!(benches, astar, flow_field);
+criterion_main!(benches);
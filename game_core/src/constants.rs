@@ -1,3 +1,8 @@
-pub const ALL_HOSTS: &'static str = "0.0.0.0";
-pub const LOCAL_HOST: &'static str = "127.0.0.1";
+pub const ALL_HOSTS: &str = "0.0.0.0";
+pub const LOCAL_HOST: &str = "127.0.0.1";
 pub const PORT: u16 = 42069;
+pub const MAX_NAME_LEN: usize = 16;
+// in chars, keeps the UTF-8 payload under the 255 byte string limit
+pub const MAX_CHAT_LEN: usize = 60;
+// sender of chat messages from the server itself, never the id of a player
+pub const SERVER_ID: u32 = u32::MAX;
@@ -1,4 +1,7 @@
 pub mod constants;
+pub mod pathfinding;
 pub mod protocol;
+pub mod sim;
 pub mod types;
 pub mod utils;
+pub mod weapons;
@@ -0,0 +1,336 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+};
+
+use crate::types::{Direction, Map, Position};
+
+const UNREACHABLE: u32 = u32::MAX;
+
+/// A map that can be walked cell by cell in the four cardinal directions
+pub trait Grid {
+    fn height(&self) -> usize;
+    fn width(&self) -> usize;
+    /// Walls, nobody ever gets through
+    fn is_blocking(&self, position: Position) -> bool;
+    /// Taken by somebody at the moment, can still be the end of a path
+    fn is_occupied(&self, _position: Position) -> bool {
+        false
+    }
+}
+
+impl Grid for Map {
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn is_blocking(&self, position: Position) -> bool {
+        self.coords[position.row as usize][position.col as usize].is_blocking()
+    }
+}
+
+/// Direction of the step from `from` to the adjacent cell `to`
+pub fn direction_to(from: Position, to: Position) -> Option<Direction> {
+    let delta = from.vector_to(to);
+    Direction::ALL.into_iter().find(|d| d.delta() == delta)
+}
+
+fn index(grid: &impl Grid, position: Position) -> usize {
+    position.row as usize * grid.width() + position.col as usize
+}
+
+fn position(grid: &impl Grid, index: usize) -> Position {
+    Position::new((index / grid.width()) as u16, (index % grid.width()) as u16)
+}
+
+fn manhattan(from: Position, to: Position) -> u32 {
+    from.manhattan_distance(to) as u32
+}
+
+/// Shortest path from `from` to `to` around walls and occupied cells, without `from` itself.
+/// The target may be occupied, so a path can lead up to a player. None if `to` can not be reached
+/// or either end is off the grid
+pub fn astar(grid: &impl Grid, from: Position, to: Position) -> Option<Vec<Position>> {
+    let (height, width) = (grid.height(), grid.width());
+    if !from.is_inside(height, width) || !to.is_inside(height, width) || grid.is_blocking(to) {
+        return None;
+    }
+
+    let cells = height * width;
+    let mut cost = vec![UNREACHABLE; cells];
+    let mut came_from: Vec<Option<usize>> = vec![None; cells];
+    // (estimated total, cost so far, cell), ties go to the cell further along, it is closer to
+    // the target
+    let mut open = BinaryHeap::from([Reverse((
+        manhattan(from, to),
+        Reverse(0),
+        index(grid, from),
+    ))]);
+    cost[index(grid, from)] = 0;
+
+    while let Some(Reverse((_, Reverse(so_far), current))) = open.pop() {
+        if so_far > cost[current] {
+            // already reached cheaper
+            continue;
+        }
+
+        let cell = position(grid, current);
+        if cell == to {
+            let mut path = vec![to];
+            let mut current = current;
+            while let Some(previous) = came_from[current] {
+                path.push(position(grid, previous));
+                current = previous;
+            }
+            path.pop();
+            path.reverse();
+            return Some(path);
+        }
+
+        for direction in Direction::CARDINAL {
+            let Some(next) = cell.neighbour(direction, height, width) else {
+                continue;
+            };
+            if grid.is_blocking(next) || (grid.is_occupied(next) && next != to) {
+                continue;
+            }
+
+            let next_index = index(grid, next);
+            let next_cost = so_far + 1;
+            if next_cost < cost[next_index] {
+                cost[next_index] = next_cost;
+                came_from[next_index] = Some(current);
+                open.push(Reverse((
+                    next_cost + manhattan(next, to),
+                    Reverse(next_cost),
+                    next_index,
+                )));
+            }
+        }
+    }
+
+    None
+}
+
+/// Dijkstra map, the number of steps from every cell to the closest goal. Built once it routes
+/// any number of walkers towards the goals, every step is uniform so it is filled breadth first
+pub struct FlowField {
+    height: usize,
+    width: usize,
+    distances: Vec<u32>,
+}
+
+impl FlowField {
+    /// Goals may be occupied, other occupied cells are routed around. Goals off the grid are
+    /// left out
+    pub fn new(grid: &impl Grid, goals: &[Position]) -> Self {
+        let (height, width) = (grid.height(), grid.width());
+        let mut distances = vec![UNREACHABLE; height * width];
+        let mut queue = VecDeque::new();
+        let reachable =
+            |&&goal: &&Position| goal.is_inside(height, width) && !grid.is_blocking(goal);
+        for &goal in goals.iter().filter(reachable) {
+            distances[index(grid, goal)] = 0;
+            queue.push_back(goal);
+        }
+
+        while let Some(cell) = queue.pop_front() {
+            let distance = distances[index(grid, cell)];
+            for direction in Direction::CARDINAL {
+                let Some(next) = cell.neighbour(direction, height, width) else {
+                    continue;
+                };
+                let next_index = index(grid, next);
+                if distances[next_index] != UNREACHABLE
+                    || grid.is_blocking(next)
+                    || grid.is_occupied(next)
+                {
+                    continue;
+                }
+
+                distances[next_index] = distance + 1;
+                queue.push_back(next);
+            }
+        }
+
+        Self {
+            height,
+            width,
+            distances,
+        }
+    }
+
+    /// Steps to the closest goal, None if no goal can be reached
+    pub fn distance(&self, position: Position) -> Option<u32> {
+        if !position.is_inside(self.height, self.width) {
+            return None;
+        }
+
+        let distance = self.distances[position.row as usize * self.width + position.col as usize];
+        (distance != UNREACHABLE).then_some(distance)
+    }
+
+    /// Step towards the closest goal, None on a goal or if no goal can be reached. `from` may be
+    /// occupied by the walker itself
+    pub fn direction(&self, from: Position) -> Option<Direction> {
+        let here = self.distance(from).unwrap_or(UNREACHABLE);
+
+        Direction::CARDINAL
+            .into_iter()
+            .filter_map(|direction| {
+                let next = from.neighbour(direction, self.height, self.width)?;
+                Some((self.distance(next)?, direction))
+            })
+            .filter(|&(distance, _)| distance < here)
+            .min_by_key(|&(distance, _)| distance)
+            .map(|(_, direction)| direction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Block;
+
+    /// Map drawn with '.' for grass and '#' for walls, players stand on the occupied cells
+    struct TestGrid {
+        map: Map,
+        occupied: Vec<Position>,
+    }
+
+    impl TestGrid {
+        fn new(rows: &[&str], occupied: &[Position]) -> Self {
+            let coords = rows
+                .iter()
+                .map(|row| {
+                    row.chars()
+                        .map(|c| match c {
+                            '#' => Block::WallVertical,
+                            _ => Block::Grass,
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+
+            Self {
+                map: Map {
+                    height: coords.len(),
+                    width: coords[0].len(),
+                    coords,
+                },
+                occupied: occupied.to_vec(),
+            }
+        }
+    }
+
+    impl Grid for TestGrid {
+        fn height(&self) -> usize {
+            self.map.height()
+        }
+
+        fn width(&self) -> usize {
+            self.map.width()
+        }
+
+        fn is_blocking(&self, position: Position) -> bool {
+            self.map.is_blocking(position)
+        }
+
+        fn is_occupied(&self, position: Position) -> bool {
+            self.occupied.contains(&position)
+        }
+    }
+
+    /// Every step of the path is a single cardinal step onto a free cell
+    fn assert_walkable(grid: &TestGrid, from: Position, path: &[Position]) {
+        let mut current = from;
+        for &next in path {
+            assert!(direction_to(current, next).is_some_and(|d| Direction::CARDINAL.contains(&d)));
+            assert!(!grid.is_blocking(next));
+            current = next;
+        }
+    }
+
+    #[test]
+    fn paths_go_around_walls() {
+        let grid = TestGrid::new(&["...", "##.", "..."], &[]);
+        let (from, to) = (Position::new(0, 0), Position::new(2, 0));
+
+        let path = astar(&grid, from, to).unwrap();
+        assert_eq!(path.len(), 6);
+        assert_eq!(path.last(), Some(&to));
+        assert_walkable(&grid, from, &path);
+    }
+
+    #[test]
+    fn paths_lead_up_to_an_occupied_target_and_around_other_players() {
+        let (blocker, target) = (Position::new(0, 1), Position::new(0, 2));
+        let grid = TestGrid::new(&["...", "..."], &[blocker, target]);
+        let from = Position::new(0, 0);
+
+        let path = astar(&grid, from, target).unwrap();
+        assert!(!path.contains(&blocker));
+        assert_eq!(path.len(), 4);
+        assert_eq!(path.last(), Some(&target));
+        assert_walkable(&grid, from, &path);
+    }
+
+    #[test]
+    fn walled_off_goals_can_not_be_reached() {
+        let grid = TestGrid::new(&["..#.", "..#."], &[]);
+        let from = Position::new(0, 0);
+
+        assert_eq!(astar(&grid, from, Position::new(1, 3)), None);
+        assert_eq!(astar(&grid, from, Position::new(0, 2)), None);
+
+        let field = FlowField::new(&grid, &[Position::new(1, 3)]);
+        assert_eq!(field.distance(from), None);
+        assert!(field.direction(from).is_none());
+    }
+
+    #[test]
+    fn ends_off_the_grid_are_never_reached() {
+        let grid = TestGrid::new(&["...", "..."], &[]);
+
+        assert_eq!(astar(&grid, Position::new(0, 0), Position::new(2, 0)), None);
+        assert_eq!(
+            astar(&grid, Position::new(0, 0), Position::new(0, 40)),
+            None
+        );
+        assert_eq!(astar(&grid, Position::new(5, 5), Position::new(0, 0)), None);
+
+        let field = FlowField::new(&grid, &[Position::new(9, 9), Position::new(0, 2)]);
+        assert_eq!(field.distance(Position::new(0, 0)), Some(2));
+        let field = FlowField::new(&grid, &[Position::new(9, 9)]);
+        assert_eq!(field.distance(Position::new(0, 0)), None);
+    }
+
+    #[test]
+    fn flow_fields_point_downhill_to_the_closest_goal() {
+        let grid = TestGrid::new(&["....", ".##.", "...."], &[]);
+        let field = FlowField::new(&grid, &[Position::new(0, 0), Position::new(2, 3)]);
+
+        assert_eq!(field.distance(Position::new(0, 0)), Some(0));
+        assert_eq!(field.distance(Position::new(0, 3)), Some(2));
+        assert_eq!(field.distance(Position::new(1, 1)), None);
+        assert_eq!(field.distance(Position::new(5, 5)), None);
+        assert!(field.direction(Position::new(0, 0)).is_none());
+
+        for row in 0..3 {
+            for col in 0..4 {
+                let cell = Position::new(row, col);
+                let (Some(distance), Some(direction)) =
+                    (field.distance(cell), field.direction(cell))
+                else {
+                    continue;
+                };
+                let next = cell.neighbour(direction, 3, 4).unwrap();
+                assert_eq!(field.distance(next), Some(distance - 1));
+            }
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use crate::types::{Coords, MapCell};
+use crate::{
+    types::{Direction, MapCell, Position, Team},
+    weapons::WeaponKind,
+};
 use proto_dryb::{Deserialize, DeserializeError, Serialize, SerializeError};
 use proto_dryb_derive::{Deserialize, Serialize};
 
@@ -16,11 +19,57 @@ pub enum ServerPacket {
     OtherPlayerMovedOutsideRadius(u32),
     PlayerDisconnected(u32),
     PlayerWasShot(u8, Direction),
-    PlayerDied(u32),
+    PlayerDied(u32, u8),
+    OtherPlayerDied(u32),
+    Respawned(Respawned),
+    KillFeed(KillFeed),
+    Scoreboard(Scoreboard),
+    ChatMessage(ChatMessage),
+    WeaponState(WeaponState),
+    MapCellChanged(MapCell),
+    Inventory(Inventory),
+    Healed(u8),
+    GameInfo(GameInfo),
+    TeamScores(TeamScores),
+    FlagStatus(FlagStatus),
+    MatchState(MatchState),
+    MatchResults(MatchResults),
+    ProjectileSpawned(Projectile),
+    ProjectileMoved(Projectile),
+    // id of the projectile and the cell it stopped on
+    ProjectileHit(u32, Position),
+    ShotFired(ShotFired),
+    ActionRejected(ActionRejected),
+    // nonce and timestamp of the client's Ping, echoed back untouched
+    Pong(u32, u64),
+    // heartbeat of the server with a nonce, the client answers it with a Pong. The server times
+    // the round trip itself, a timestamp from the client could be faked
+    Ping(u32),
+    // sent right before the server closes every connection
+    ServerShutdown(ServerShutdown),
+    // one line of the answer to an admin command
+    AdminReply(String),
+    // why the player was removed, the connection is closed right after
+    Kicked(String),
+    // hp set outright by the server
+    HpChanged(u8),
+    // answer to a join once the player cap is reached, the connection is closed right after
+    ServerFull,
 }
 
-pub fn generate_player_died_payload(buf: &mut [u8], by_id: u32) -> Result<usize, SerializeError> {
-    Packet::Server(ServerPacket::PlayerDied(by_id)).serialize(buf)
+pub fn generate_player_died_payload(
+    buf: &mut [u8],
+    by_id: u32,
+    respawn_in: u8,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::PlayerDied(by_id, respawn_in)).serialize(buf)
+}
+
+pub fn generate_other_player_died_payload(
+    buf: &mut [u8],
+    id: u32,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::OtherPlayerDied(id)).serialize(buf)
 }
 
 pub fn generate_shoot_payload(
@@ -51,16 +100,25 @@ pub fn generate_move_outside_radius_notify_payload(
 
 #[derive(Serialize, Deserialize)]
 pub struct OtherPlayerMoved {
-    pub coords: Coords,
+    pub coords: Position,
     pub id: u32,
+    pub name: String,
+    pub team: Option<Team>,
 }
 
 pub fn generate_move_notify_payload(
     buf: &mut [u8],
-    coords: Coords,
+    coords: Position,
     id: u32,
+    name: String,
+    team: Option<Team>,
 ) -> Result<usize, SerializeError> {
-    let opm = OtherPlayerMoved { coords, id };
+    let opm = OtherPlayerMoved {
+        coords,
+        id,
+        name,
+        team,
+    };
     let packet = Packet::Server(ServerPacket::OtherPlayerMoved(opm));
 
     packet.serialize(buf)
@@ -68,94 +126,110 @@ pub fn generate_move_notify_payload(
 
 #[derive(Serialize, Deserialize)]
 pub enum ClientPacket {
-    Move(Direction),
+    // sequence number of the move, the client counts up from 1
+    Move(u32, Direction),
     Shoot(Direction),
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
-pub enum Direction {
-    Up,
-    Right,
-    Down,
-    Left,
-}
-
-impl TryFrom<char> for Direction {
-    type Error = ();
-    fn try_from(value: char) -> Result<Self, Self::Error> {
-        match value {
-            'w' | 'k' => Ok(Self::Up),
-            'd' | 'l' => Ok(Self::Right),
-            's' | 'j' => Ok(Self::Down),
-            'a' | 'h' => Ok(Self::Left),
-            _ => Err(()),
-        }
-    }
+    Scoreboard,
+    Join(String),
+    Chat(ChatChannel, String),
+    SwitchWeapon(WeaponKind),
+    Reload,
+    UseHealthPack,
+    // session token from NewClient, joins anew under the name once the session has expired
+    Resume(u64, String),
+    // nonce and the sender's unix time in millis, the server echoes both back in a Pong
+    Ping(u32, u64),
+    // nonce of the server's Ping, echoed back untouched
+    Pong(u32),
+    // password and a command for the admin console, allowed before joining as well
+    Admin(String, String),
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Player {
     pub id: u32,
-    pub coords: Coords,
+    pub coords: Position,
+    pub name: String,
+    pub team: Option<Team>,
 }
 
 impl Player {
-    pub fn new(id: u32, coords: Coords) -> Self {
-        Self { id, coords }
+    pub fn new(id: u32, coords: Position, name: String, team: Option<Team>) -> Self {
+        Self {
+            id,
+            coords,
+            name,
+            team,
+        }
     }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct NewClient {
     pub id: u32,
-    pub coords: Coords,
+    pub name: String,
+    pub coords: Position,
     pub hp: u8,
+    pub max_hp: u8,
     pub radius: u8,
-    pub weapon_range: u8,
+    pub weapon: WeaponState,
     pub visible_coords: Vec<MapCell>,
     pub players: Vec<Player>,
+    // lets the client resume the same player after losing the connection
+    pub token: u64,
 }
 
 impl NewClient {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         id: u32,
-        coords: Coords,
+        name: String,
+        coords: Position,
         visible_coords: Vec<MapCell>,
         radius: u8,
-        hp: u8,
-        weapon_range: u8,
+        (hp, max_hp): (u8, u8),
+        weapon: WeaponState,
         players: Vec<Player>,
+        token: u64,
     ) -> Self {
         Self {
             id,
+            name,
             coords,
             hp,
+            max_hp,
             radius,
-            weapon_range,
+            weapon,
             visible_coords,
             players,
+            token,
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn generate_initial_payload(
     buf: &mut [u8],
     id: u32,
-    coords: Coords,
+    name: String,
+    coords: Position,
     radius: u8,
-    hp: u8,
-    weapon_range: u8,
+    (hp, max_hp): (u8, u8),
+    weapon: WeaponState,
     visible_coords: Vec<MapCell>,
     players: Vec<Player>,
+    token: u64,
 ) -> Result<usize, SerializeError> {
     let packet = Packet::Server(ServerPacket::NewClientCoordsVisibleMap(NewClient::new(
         id,
+        name,
         coords,
         visible_coords,
         radius,
-        hp,
-        weapon_range,
+        (hp, max_hp),
+        weapon,
         players,
+        token,
     )));
 
     packet.serialize(buf)
@@ -163,32 +237,484 @@ pub fn generate_initial_payload(
 
 #[derive(Serialize, Deserialize)]
 pub struct NewCoords {
-    pub center: Coords,
+    pub center: Position,
     pub coords: Vec<MapCell>,
     pub players: Vec<Player>,
+    // sequence number of the last move the server has handled, whether it was applied or not
+    pub ack: u32,
 }
 
 impl NewCoords {
-    fn new(center: Coords, coords: Vec<MapCell>, players: Vec<Player>) -> Self {
+    fn new(center: Position, coords: Vec<MapCell>, players: Vec<Player>, ack: u32) -> Self {
         Self {
             center,
             coords,
             players,
+            ack,
         }
     }
 }
 
 pub fn generate_new_coords_payload(
     buf: &mut [u8],
-    new_player_coord: Coords,
+    new_player_coord: Position,
     new_visiple_coord: Vec<MapCell>,
     visible_players: Vec<Player>,
+    ack: u32,
 ) -> Result<usize, SerializeError> {
     let packet = Packet::Server(ServerPacket::NewCoords(NewCoords::new(
         new_player_coord,
         new_visiple_coord,
         visible_players,
+        ack,
     )));
 
     packet.serialize(buf)
 }
+
+#[derive(Serialize, Deserialize)]
+pub struct Respawned {
+    pub coords: Position,
+    pub hp: u8,
+    pub visible_coords: Vec<MapCell>,
+    pub players: Vec<Player>,
+}
+
+pub fn generate_respawned_payload(
+    buf: &mut [u8],
+    coords: Position,
+    hp: u8,
+    visible_coords: Vec<MapCell>,
+    players: Vec<Player>,
+) -> Result<usize, SerializeError> {
+    let packet = Packet::Server(ServerPacket::Respawned(Respawned {
+        coords,
+        hp,
+        visible_coords,
+        players,
+    }));
+
+    packet.serialize(buf)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct KillFeed {
+    pub killer: u32,
+    pub killer_name: String,
+    pub victim: u32,
+    pub victim_name: String,
+}
+
+pub fn generate_kill_feed_payload(
+    buf: &mut [u8],
+    (killer, killer_name): (u32, String),
+    (victim, victim_name): (u32, String),
+) -> Result<usize, SerializeError> {
+    let kill_feed = KillFeed {
+        killer,
+        killer_name,
+        victim,
+        victim_name,
+    };
+
+    Packet::Server(ServerPacket::KillFeed(kill_feed)).serialize(buf)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Score {
+    pub id: u32,
+    pub name: String,
+    pub team: Option<Team>,
+    pub kills: u16,
+    pub deaths: u16,
+    pub streak: u16,
+    pub best_streak: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Scoreboard {
+    pub scores: Vec<Score>,
+}
+
+pub fn generate_scoreboard_payload(
+    buf: &mut [u8],
+    scores: Vec<Score>,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::Scoreboard(Scoreboard { scores })).serialize(buf)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ChatChannel {
+    Proximity,
+    Global,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub from: u32,
+    pub from_name: String,
+    pub channel: ChatChannel,
+    pub text: String,
+}
+
+pub fn generate_chat_message_payload(
+    buf: &mut [u8],
+    (from, from_name): (u32, String),
+    channel: ChatChannel,
+    text: String,
+) -> Result<usize, SerializeError> {
+    let message = ChatMessage {
+        from,
+        from_name,
+        channel,
+        text,
+    };
+
+    Packet::Server(ServerPacket::ChatMessage(message)).serialize(buf)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WeaponState {
+    pub kind: WeaponKind,
+    pub ammo: u8,
+    pub reserve: u8,
+    pub reloading: bool,
+}
+
+pub fn generate_weapon_state_payload(
+    buf: &mut [u8],
+    weapon: WeaponState,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::WeaponState(weapon)).serialize(buf)
+}
+
+pub fn generate_map_cell_changed_payload(
+    buf: &mut [u8],
+    cell: MapCell,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::MapCellChanged(cell)).serialize(buf)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Inventory {
+    pub armor: u8,
+    pub health_packs: u8,
+    pub weapons: Vec<WeaponKind>,
+}
+
+pub fn generate_inventory_payload(
+    buf: &mut [u8],
+    inventory: Inventory,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::Inventory(inventory)).serialize(buf)
+}
+
+pub fn generate_healed_payload(buf: &mut [u8], hp: u8) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::Healed(hp)).serialize(buf)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum GameMode {
+    #[default]
+    FreeForAll,
+    TeamDeathmatch,
+    CaptureTheFlag,
+}
+
+impl GameMode {
+    pub fn has_teams(self) -> bool {
+        self != GameMode::FreeForAll
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GameMode::FreeForAll => "FREE FOR ALL",
+            GameMode::TeamDeathmatch => "TEAM DEATHMATCH",
+            GameMode::CaptureTheFlag => "CAPTURE THE FLAG",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GameInfo {
+    pub mode: GameMode,
+    // None in free for all
+    pub team: Option<Team>,
+}
+
+pub fn generate_game_info_payload(
+    buf: &mut [u8],
+    mode: GameMode,
+    team: Option<Team>,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::GameInfo(GameInfo { mode, team })).serialize(buf)
+}
+
+/// Kills in team deathmatch, captures in capture the flag
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+pub struct TeamScores {
+    pub red: u16,
+    pub blue: u16,
+}
+
+impl TeamScores {
+    pub fn get(&self, team: Team) -> u16 {
+        match team {
+            Team::Red => self.red,
+            Team::Blue => self.blue,
+        }
+    }
+
+    pub fn add_point(&mut self, team: Team) {
+        let score = match team {
+            Team::Red => &mut self.red,
+            Team::Blue => &mut self.blue,
+        };
+        *score = score.saturating_add(1);
+    }
+}
+
+pub fn generate_team_scores_payload(
+    buf: &mut [u8],
+    scores: TeamScores,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::TeamScores(scores)).serialize(buf)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum FlagState {
+    AtBase,
+    // id of the carrier
+    Carried(u32),
+    Dropped(Position),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct FlagStatus {
+    pub team: Team,
+    pub state: FlagState,
+}
+
+pub fn generate_flag_status_payload(
+    buf: &mut [u8],
+    status: FlagStatus,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::FlagStatus(status)).serialize(buf)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum MatchPhase {
+    // waiting for enough players
+    #[default]
+    Lobby,
+    // free play, scores are reset when it ends
+    Warmup,
+    Live,
+    // results are shown, nobody can act until the reset
+    PostMatch,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MatchState {
+    pub phase: MatchPhase,
+    // 0 if the phase has no end time
+    pub seconds_left: u16,
+    pub players: u8,
+    pub min_players: u8,
+}
+
+pub fn generate_match_state_payload(
+    buf: &mut [u8],
+    state: MatchState,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::MatchState(state)).serialize(buf)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Winner {
+    Draw,
+    Team(Team),
+    // id of the player
+    Player(u32),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MatchResults {
+    pub winner: Winner,
+    pub team_scores: TeamScores,
+    pub scores: Vec<Score>,
+}
+
+pub fn generate_match_results_payload(
+    buf: &mut [u8],
+    results: MatchResults,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::MatchResults(results)).serialize(buf)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Projectile {
+    pub id: u32,
+    pub kind: WeaponKind,
+    pub direction: Direction,
+    pub coords: Position,
+}
+
+pub fn generate_projectile_spawned_payload(
+    buf: &mut [u8],
+    projectile: Projectile,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::ProjectileSpawned(projectile)).serialize(buf)
+}
+
+pub fn generate_projectile_moved_payload(
+    buf: &mut [u8],
+    projectile: Projectile,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::ProjectileMoved(projectile)).serialize(buf)
+}
+
+pub fn generate_projectile_hit_payload(
+    buf: &mut [u8],
+    id: u32,
+    coords: Position,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::ProjectileHit(id, coords)).serialize(buf)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ShotFired {
+    pub shooter: u32,
+    // first cell of the path, it runs `range` cells straight on from here
+    pub origin: Position,
+    pub direction: Direction,
+    pub range: u8,
+    // the player the shot stopped on
+    pub hit: Option<u32>,
+}
+
+pub fn generate_shot_fired_payload(
+    buf: &mut [u8],
+    shot: ShotFired,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::ShotFired(shot)).serialize(buf)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ActionKind {
+    Move,
+    Shoot,
+    SwitchWeapon,
+    Reload,
+    UseHealthPack,
+}
+
+/// Why the server refused an action
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum RejectReason {
+    PlayerGone,
+    PlayerDead,
+    OutsideMap,
+    Wall,
+    SqueezeBetweenWalls,
+    Occupied,
+    Reloading,
+    CoolingDown,
+    OutOfAmmo,
+    WeaponNotOwned,
+    NoHealthPacks,
+    FullHealth,
+    // nobody can act while the results are shown
+    MatchOver,
+    // the client sends this kind of action faster than the server takes them
+    RateLimited,
+    // the step came before the move cooldown ran out
+    MovingTooFast,
+}
+
+impl RejectReason {
+    pub fn message(self) -> &'static str {
+        match self {
+            RejectReason::PlayerGone => "Player is gone",
+            RejectReason::PlayerDead => "Player is dead",
+            RejectReason::OutsideMap => "New position is outside the map",
+            RejectReason::Wall => "Cell is a wall",
+            RejectReason::SqueezeBetweenWalls => "Cannot squeeze between walls",
+            RejectReason::Occupied => "Cell is occupied",
+            RejectReason::Reloading => "Weapon is reloading",
+            RejectReason::CoolingDown => "Weapon is cooling down",
+            RejectReason::OutOfAmmo => "Out of ammo",
+            RejectReason::WeaponNotOwned => "Weapon is not owned",
+            RejectReason::NoHealthPacks => "No health packs left",
+            RejectReason::FullHealth => "Already at full health",
+            RejectReason::MatchOver => "Match is over",
+            RejectReason::RateLimited => "Too many actions, slow down",
+            RejectReason::MovingTooFast => "Moving too fast",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ActionRejected {
+    pub action: ActionKind,
+    pub reason_code: RejectReason,
+}
+
+pub fn generate_action_rejected_payload(
+    buf: &mut [u8],
+    action: ActionKind,
+    reason_code: RejectReason,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::ActionRejected(ActionRejected {
+        action,
+        reason_code,
+    }))
+    .serialize(buf)
+}
+
+pub fn generate_pong_payload(
+    buf: &mut [u8],
+    nonce: u32,
+    timestamp: u64,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::Pong(nonce, timestamp)).serialize(buf)
+}
+
+pub fn generate_ping_payload(buf: &mut [u8], nonce: u32) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::Ping(nonce)).serialize(buf)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerShutdown {
+    pub reason: String,
+    // seconds until the server is expected back, None if it is not
+    pub reconnect_in: Option<u16>,
+}
+
+pub fn generate_server_shutdown_payload(
+    buf: &mut [u8],
+    reason: String,
+    reconnect_in: Option<u16>,
+) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::ServerShutdown(ServerShutdown {
+        reason,
+        reconnect_in,
+    }))
+    .serialize(buf)
+}
+
+pub fn generate_admin_reply_payload(buf: &mut [u8], line: String) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::AdminReply(line)).serialize(buf)
+}
+
+pub fn generate_kicked_payload(buf: &mut [u8], reason: String) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::Kicked(reason)).serialize(buf)
+}
+
+pub fn generate_hp_changed_payload(buf: &mut [u8], hp: u8) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::HpChanged(hp)).serialize(buf)
+}
+
+pub fn generate_server_full_payload(buf: &mut [u8]) -> Result<usize, SerializeError> {
+    Packet::Server(ServerPacket::ServerFull).serialize(buf)
+}
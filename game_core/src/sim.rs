@@ -0,0 +1,1782 @@
+use std::{
+    cmp::{max, min},
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    pathfinding::Grid,
+    protocol::{self, ActionKind, Inventory, RejectReason, WeaponState},
+    types::{self, Block, Direction, ItemKind, Map, Position, Team, Vector},
+    utils,
+    weapons::WeaponKind,
+};
+
+pub const PLAYER_HP: u8 = 10;
+// How far every player sees, queries for the players seeing a cell rely on nobody seeing further
+pub const PLAYER_VIEW_RADIUS: u8 = 5;
+pub const MAX_ARMOR: u8 = 10;
+pub const ARMOR_PICKUP: u8 = 5;
+pub const MAX_HEALTH_PACKS: u8 = 3;
+pub const HEALTH_PACK_HP: u8 = 5;
+// Shortest time between two steps of a player, clients pace their moves by it as well
+pub const MOVE_COOLDOWN: Duration = Duration::from_millis(50);
+// How much sooner than MOVE_COOLDOWN a step may arrive, jitter bunches up steps a client paced
+const MOVE_COOLDOWN_TOLERANCE: Duration = Duration::from_millis(15);
+// Side of the square of cells one spatial hash bucket covers
+const SPATIAL_BUCKET_SIZE: usize = 8;
+// random cells tried for a spawn before the whole half is searched for a free one
+const SPAWN_TRIES: usize = 32;
+
+/// What a player asks for, the id is the player doing it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Move(u32, Direction),
+    Shoot(u32, Direction),
+    SwitchWeapon(u32, WeaponKind),
+    Reload(u32),
+    UseHealthPack(u32),
+}
+
+impl Action {
+    pub fn player(self) -> u32 {
+        match self {
+            Action::Move(id, _)
+            | Action::Shoot(id, _)
+            | Action::SwitchWeapon(id, _)
+            | Action::Reload(id)
+            | Action::UseHealthPack(id) => id,
+        }
+    }
+
+    pub fn kind(self) -> ActionKind {
+        match self {
+            Action::Move(..) => ActionKind::Move,
+            Action::Shoot(..) => ActionKind::Shoot,
+            Action::SwitchWeapon(..) => ActionKind::SwitchWeapon,
+            Action::Reload(_) => ActionKind::Reload,
+            Action::UseHealthPack(_) => ActionKind::UseHealthPack,
+        }
+    }
+}
+
+/// What came out of an action or of time passing
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Moved {
+        id: u32,
+        from: Position,
+        to: Position,
+    },
+    /// An item was taken from the cell or put on it
+    CellChanged(Position),
+    InventoryChanged(u32),
+    WeaponChanged(u32),
+    Healed {
+        id: u32,
+        hp: u8,
+    },
+    Hit {
+        id: u32,
+        damage: u8,
+        direction: Direction,
+    },
+    Killed {
+        id: u32,
+        by: u32,
+    },
+    ProjectileSpawned(protocol::Projectile),
+    ProjectileMoved {
+        projectile: protocol::Projectile,
+        from: Position,
+    },
+    /// The projectile stopped on a player, in front of a wall or at the end of its range
+    ProjectileHit {
+        id: u32,
+        coords: Position,
+    },
+    /// Outcome of a projectile which has flown at least one cell. The path runs `range` cells
+    /// straight on from `origin`, the first one of them, `hit` is the player it stopped on
+    ShotFired {
+        shooter: u32,
+        origin: Position,
+        direction: Direction,
+        range: u8,
+        hit: Option<u32>,
+    },
+    Rejected {
+        action: Action,
+        reason: RejectReason,
+    },
+}
+
+pub struct Weapon {
+    pub kind: WeaponKind,
+    pub ammo: u8,
+    pub reserve: u8,
+    // end of the cooldown or of the reload
+    pub ready_at: Instant,
+    pub reloading: bool,
+}
+
+impl Weapon {
+    pub fn new(kind: WeaponKind, now: Instant) -> Self {
+        let spec = kind.spec();
+        Self {
+            kind,
+            ammo: spec.magazine,
+            reserve: spec.max_reserve.unwrap_or(0),
+            ready_at: now,
+            reloading: false,
+        }
+    }
+
+    pub fn has_reserve(&self) -> bool {
+        self.kind.spec().max_reserve.is_none() || self.reserve > 0
+    }
+
+    /// Adds a magazine worth of ammo, false if the reserve is endless or already full
+    fn refill_reserve(&mut self) -> bool {
+        let spec = self.kind.spec();
+        match spec.max_reserve {
+            Some(max_reserve) if self.reserve < max_reserve => {
+                self.reserve = min(max_reserve, self.reserve.saturating_add(spec.magazine));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn fire(&mut self, now: Instant) -> Result<(), RejectReason> {
+        if self.reloading {
+            return Err(RejectReason::Reloading);
+        }
+        if now < self.ready_at {
+            return Err(RejectReason::CoolingDown);
+        }
+        if self.ammo == 0 {
+            self.start_reload(now);
+            return Err(RejectReason::OutOfAmmo);
+        }
+
+        self.ammo -= 1;
+        self.ready_at = now + self.kind.spec().cooldown;
+        if self.ammo == 0 {
+            self.start_reload(now);
+        }
+
+        Ok(())
+    }
+
+    fn start_reload(&mut self, now: Instant) -> bool {
+        let spec = self.kind.spec();
+        if self.reloading || self.ammo == spec.magazine || !self.has_reserve() {
+            return false;
+        }
+
+        self.reloading = true;
+        self.ready_at = max(self.ready_at, now + spec.reload);
+
+        true
+    }
+
+    /// Returns true if a reload has just been completed
+    fn finish_reload(&mut self, now: Instant) -> bool {
+        if !self.reloading || now < self.ready_at {
+            return false;
+        }
+
+        let spec = self.kind.spec();
+        let missing = spec.magazine - self.ammo;
+        let loaded = match spec.max_reserve {
+            Some(_) => min(missing, self.reserve),
+            None => missing,
+        };
+        self.ammo += loaded;
+        if spec.max_reserve.is_some() {
+            self.reserve -= loaded;
+        }
+        self.reloading = false;
+
+        true
+    }
+
+    pub fn state(&self) -> WeaponState {
+        WeaponState {
+            kind: self.kind,
+            ammo: self.ammo,
+            reserve: self.reserve,
+            reloading: self.reloading,
+        }
+    }
+}
+
+fn starting_weapons(now: Instant) -> Vec<Weapon> {
+    vec![Weapon::new(WeaponKind::Pistol, now)]
+}
+
+pub struct Player {
+    pub id: u32,
+    pub name: String,
+    // None in free for all
+    pub team: Option<Team>,
+    pub coords: Position,
+    pub radius: u8,
+    pub hp: u8,
+    pub weapons: Vec<Weapon>,
+    pub current_weapon: usize,
+    pub armor: u8,
+    pub health_packs: u8,
+    // dead players are off the map but stay where they died until they respawn
+    pub dead: bool,
+    next_move_at: Instant,
+}
+
+impl Player {
+    fn new(id: u32, name: String, team: Option<Team>, coords: Position, now: Instant) -> Self {
+        Self {
+            id,
+            name,
+            team,
+            coords,
+            radius: PLAYER_VIEW_RADIUS,
+            hp: PLAYER_HP,
+            weapons: starting_weapons(now),
+            current_weapon: 0,
+            armor: 0,
+            health_packs: 0,
+            dead: false,
+            next_move_at: now,
+        }
+    }
+
+    pub fn current_weapon(&self) -> &Weapon {
+        &self.weapons[self.current_weapon]
+    }
+
+    pub fn inventory(&self) -> Inventory {
+        Inventory {
+            armor: self.armor,
+            health_packs: self.health_packs,
+            weapons: self.weapons.iter().map(|w| w.kind).collect(),
+        }
+    }
+
+    /// How other players are told about this one
+    pub fn visible(&self) -> protocol::Player {
+        protocol::Player::new(self.id, self.coords, self.name.clone(), self.team)
+    }
+
+    /// Everything picked up is lost on death
+    fn reset_loadout(&mut self, now: Instant) {
+        self.weapons = starting_weapons(now);
+        self.current_weapon = 0;
+        self.armor = 0;
+        self.health_packs = 0;
+    }
+
+    /// Returns false if the item is of no use right now and should stay on the map
+    fn pick_up(&mut self, item: ItemKind, now: Instant) -> bool {
+        match item {
+            ItemKind::HealthPack if self.health_packs < MAX_HEALTH_PACKS => {
+                self.health_packs += 1;
+                true
+            }
+            ItemKind::Armor if self.armor < MAX_ARMOR => {
+                self.armor = min(MAX_ARMOR, self.armor + ARMOR_PICKUP);
+                true
+            }
+            ItemKind::HealthPack | ItemKind::Armor => false,
+            ItemKind::Ammo => {
+                // every weapon gets a refill, no short circuit
+                let mut refilled = false;
+                for weapon in self.weapons.iter_mut() {
+                    refilled |= weapon.refill_reserve();
+                }
+                refilled
+            }
+            ItemKind::Weapon(kind) => match self.weapons.iter_mut().find(|w| w.kind == kind) {
+                Some(weapon) => weapon.refill_reserve(),
+                None => {
+                    self.weapons.push(Weapon::new(kind, now));
+                    true
+                }
+            },
+        }
+    }
+
+    /// Returns the hp healed
+    fn use_health_pack(&mut self) -> Result<u8, RejectReason> {
+        if self.health_packs == 0 {
+            return Err(RejectReason::NoHealthPacks);
+        }
+        if self.hp >= PLAYER_HP {
+            return Err(RejectReason::FullHealth);
+        }
+
+        let healed = min(HEALTH_PACK_HP, PLAYER_HP - self.hp);
+        self.hp += healed;
+        self.health_packs -= 1;
+
+        Ok(healed)
+    }
+
+    /// Armor soaks up damage first, returns what is left for the hp
+    fn absorb_damage(&mut self, damage: u8) -> u8 {
+        let absorbed = min(self.armor, damage);
+        self.armor -= absorbed;
+
+        damage - absorbed
+    }
+}
+
+/// A shot on its way, every lane of a weapon fires one
+pub struct Projectile {
+    pub id: u32,
+    pub owner: u32,
+    // of the owner when it was fired, there is no friendly fire
+    team: Option<Team>,
+    pub kind: WeaponKind,
+    pub direction: Direction,
+    origin: Position,
+    lane: i16,
+    // cells flown from the origin
+    travelled: u8,
+    pierced: u8,
+    pub coords: Position,
+    next_step_at: Instant,
+}
+
+impl Projectile {
+    /// How clients are told about this one
+    pub fn visible(&self) -> protocol::Projectile {
+        protocol::Projectile {
+            id: self.id,
+            kind: self.kind,
+            direction: self.direction,
+            coords: self.coords,
+        }
+    }
+}
+
+pub struct Cell {
+    pub block: Block,
+    pub item: Option<ItemKind>,
+    pub flag: Option<Team>,
+    // only the world moves players around
+    occupant: Option<u32>,
+}
+
+impl Cell {
+    /// Id of the player standing here
+    pub fn occupant(&self) -> Option<u32> {
+        self.occupant
+    }
+
+    /// What clients are shown, flags cover items which lie on top of the block
+    pub fn visible_block(&self) -> Block {
+        self.flag
+            .map(Block::Flag)
+            .or(self.item.map(Block::Item))
+            .unwrap_or(self.block)
+    }
+}
+
+pub struct Arena {
+    pub height: usize,
+    pub width: usize,
+    cells: Vec<Vec<Cell>>,
+}
+
+impl Grid for Arena {
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn is_blocking(&self, position: Position) -> bool {
+        self.cell(position).block.is_blocking()
+    }
+
+    fn is_occupied(&self, position: Position) -> bool {
+        self.cell(position).occupant.is_some()
+    }
+}
+
+impl Arena {
+    pub fn from_map(map: &Map) -> Self {
+        let cells = map
+            .coords
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|&block| Cell {
+                        block,
+                        item: None,
+                        flag: None,
+                        occupant: None,
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            height: map.height,
+            width: map.width,
+            cells,
+        }
+    }
+
+    /// Panics for positions off the arena
+    pub fn cell(&self, position: Position) -> &Cell {
+        &self.cells[position.row as usize][position.col as usize]
+    }
+
+    /// Panics for positions off the arena
+    pub fn cell_mut(&mut self, position: Position) -> &mut Cell {
+        &mut self.cells[position.row as usize][position.col as usize]
+    }
+
+    fn get_mut(&mut self, position: Position) -> Option<&mut Cell> {
+        self.cells
+            .get_mut(position.row as usize)
+            .and_then(|row| row.get_mut(position.col as usize))
+    }
+
+    /// Whether a diagonal step from `from` to `to` slips between two walls touching at their
+    /// corners. Both cells have to be on the arena
+    pub fn cuts_corner(&self, from: Position, to: Position) -> bool {
+        let step = from.vector_to(to);
+
+        step.rows != 0
+            && step.cols != 0
+            && self
+                .cell(Position::new(to.row, from.col))
+                .block
+                .is_blocking()
+            && self
+                .cell(Position::new(from.row, to.col))
+                .block
+                .is_blocking()
+    }
+
+    /// A random cell without a player or a wall, the whole half is searched when a few rolls
+    /// miss. Team players only spawn on their own half, None if every cell of it is taken
+    pub fn random_free_coords(&self, team: Option<Team>) -> Option<Position> {
+        let is_free = |position: Position| {
+            let cell = self.cell(position);
+            cell.occupant.is_none()
+                && !cell.block.is_blocking()
+                && self.is_team_side(team, position.col)
+        };
+
+        (0..SPAWN_TRIES)
+            .map(|_| utils::generate_random_position(self.height, self.width))
+            .find(|&position| is_free(position))
+            .or_else(|| {
+                (0..self.height as u16)
+                    .flat_map(|row| (0..self.width as u16).map(move |col| Position::new(row, col)))
+                    .find(|&position| is_free(position))
+            })
+    }
+
+    /// Whether no wall stands on the straight line between the two, the ends left out
+    pub fn line_of_sight(&self, from: Position, to: Position) -> bool {
+        let step = from.vector_to(to);
+        let (rows, cols) = (step.rows.abs(), step.cols.abs());
+        let (row_step, col_step) = (step.rows.signum(), step.cols.signum());
+        let (mut row, mut col) = (from.row as i16, from.col as i16);
+        // Bresenham, the error tracks how far the line is off the cell centers
+        let mut error = cols - rows;
+
+        loop {
+            let twice = error * 2;
+            if twice > -rows {
+                error -= rows;
+                col += col_step;
+            }
+            if twice < cols {
+                error += cols;
+                row += row_step;
+            }
+            let cell = Position::new(row as u16, col as u16);
+            if cell == to {
+                return true;
+            }
+            if self.cell(cell).block.is_blocking() {
+                return false;
+            }
+        }
+    }
+
+    /// Red owns the left half of the arena and blue the right one
+    pub fn is_team_side(&self, team: Option<Team>, col: u16) -> bool {
+        let half = self.width as u16 / 2;
+        match team {
+            None => true,
+            Some(Team::Red) => col < half,
+            Some(Team::Blue) => col >= half,
+        }
+    }
+
+    pub fn flag_home(&self, team: Team) -> Position {
+        let row = self.height as u16 / 2;
+        match team {
+            Team::Red => Position::new(row, 1),
+            Team::Blue => Position::new(row, self.width as u16 - 2),
+        }
+    }
+
+    /// Up to `count` random cells, no cell is picked twice
+    pub fn random_distinct_coords(&self, count: usize) -> Vec<Position> {
+        let count = min(count, self.height * self.width);
+        let mut picked = Vec::with_capacity(count);
+        while picked.len() < count {
+            let coords = utils::generate_random_position(self.height, self.width);
+            if !picked.contains(&coords) {
+                picked.push(coords);
+            }
+        }
+
+        picked
+    }
+}
+
+/// Uniform grid of buckets over the arena holding every player by position, answers who is
+/// around a cell without going through all of them
+struct SpatialHash {
+    rows: usize,
+    cols: usize,
+    buckets: Vec<Vec<(u32, Position)>>,
+    positions: HashMap<u32, Position>,
+}
+
+impl SpatialHash {
+    fn new(height: usize, width: usize) -> Self {
+        let rows = height.div_ceil(SPATIAL_BUCKET_SIZE).max(1);
+        let cols = width.div_ceil(SPATIAL_BUCKET_SIZE).max(1);
+
+        Self {
+            rows,
+            cols,
+            buckets: vec![vec![]; rows * cols],
+            positions: HashMap::new(),
+        }
+    }
+
+    fn bucket(&self, position: Position) -> usize {
+        let row = min(position.row as usize / SPATIAL_BUCKET_SIZE, self.rows - 1);
+        let col = min(position.col as usize / SPATIAL_BUCKET_SIZE, self.cols - 1);
+
+        row * self.cols + col
+    }
+
+    /// Adds the player or moves it if it is already there
+    fn set(&mut self, id: u32, coords: Position) {
+        self.remove(id);
+        let bucket = self.bucket(coords);
+        self.buckets[bucket].push((id, coords));
+        self.positions.insert(id, coords);
+    }
+
+    fn remove(&mut self, id: u32) {
+        let Some(coords) = self.positions.remove(&id) else {
+            return;
+        };
+        let bucket = self.bucket(coords);
+        self.buckets[bucket].retain(|&(other, _)| other != id);
+    }
+
+    /// Players standing within `radius` of `center`
+    fn within(&self, center: Position, radius: u8) -> impl Iterator<Item = u32> + '_ {
+        let (row, col, r) = (center.row as usize, center.col as usize, radius as usize);
+        let rows = row.saturating_sub(r) / SPATIAL_BUCKET_SIZE
+            ..=min((row + r) / SPATIAL_BUCKET_SIZE, self.rows - 1);
+        let cols = col.saturating_sub(r) / SPATIAL_BUCKET_SIZE
+            ..=min((col + r) / SPATIAL_BUCKET_SIZE, self.cols - 1);
+
+        rows.flat_map(move |row| cols.clone().map(move |col| row * self.cols + col))
+            .flat_map(|bucket| self.buckets[bucket].iter())
+            .filter(move |&&(_, coords)| utils::is_inside_circle(center, radius, coords))
+            .map(|&(id, _)| id)
+    }
+}
+
+/// Movement, collision, shooting and visibility rules. Performs no I/O, whoever owns it turns
+/// the events into packets. Players are kept in id order so every run plays out the same
+pub struct World {
+    pub arena: Arena,
+    players: BTreeMap<u32, Player>,
+    projectiles: BTreeMap<u32, Projectile>,
+    projectile_counter: u32,
+    spatial: SpatialHash,
+    // time of the last tick, cooldowns, reloads and projectile steps are measured against it
+    now: Instant,
+}
+
+impl World {
+    pub fn new(arena: Arena, now: Instant) -> Self {
+        Self {
+            spatial: SpatialHash::new(arena.height, arena.width),
+            arena,
+            players: BTreeMap::new(),
+            projectiles: BTreeMap::new(),
+            projectile_counter: 0,
+            now,
+        }
+    }
+
+    pub fn player(&self, id: u32) -> Option<&Player> {
+        self.players.get(&id)
+    }
+
+    pub fn players(&self) -> impl Iterator<Item = &Player> {
+        self.players.values()
+    }
+
+    pub fn projectiles(&self) -> impl Iterator<Item = &Projectile> {
+        self.projectiles.values()
+    }
+
+    /// Players standing within `radius` of `center`, dead ones included
+    pub fn players_within(&self, center: Position, radius: u8) -> impl Iterator<Item = &Player> {
+        self.spatial
+            .within(center, radius)
+            .filter_map(|id| self.players.get(&id))
+    }
+
+    /// Players alive around `coords` but `except_id`, as the one standing there sees them
+    pub fn visible_players(
+        &self,
+        coords: Position,
+        radius: u8,
+        except_id: u32,
+    ) -> Vec<protocol::Player> {
+        self.players_within(coords, radius)
+            .filter(|p| p.id != except_id && !p.dead)
+            .map(Player::visible)
+            .collect()
+    }
+
+    /// Cells inside the circle of `radius` around `coords`
+    pub fn visible_cells(&self, coords: Position, radius: u8) -> Vec<types::MapCell> {
+        let r = radius as i16;
+        (-r..=r)
+            .flat_map(|rows| (-r..=r).map(move |cols| Vector::new(rows, cols)))
+            .filter_map(|vector| coords.offset(vector, self.arena.height, self.arena.width))
+            .filter(|&cell| utils::is_inside_circle(coords, radius, cell))
+            .map(|cell| types::MapCell::new(self.arena.cell(cell).visible_block(), cell))
+            .collect()
+    }
+
+    /// Puts a new player on a free cell
+    pub fn add_player(&mut self, id: u32, name: String, team: Option<Team>, coords: Position) {
+        self.arena.cell_mut(coords).occupant = Some(id);
+        self.spatial.set(id, coords);
+        self.players
+            .insert(id, Player::new(id, name, team, coords, self.now));
+    }
+
+    pub fn remove_player(&mut self, id: u32) -> Option<Player> {
+        let player = self.players.remove(&id)?;
+        self.spatial.remove(id);
+        self.vacate(id, player.coords);
+
+        Some(player)
+    }
+
+    /// Brings a player back to life with full hp and the starting loadout on a free cell
+    pub fn respawn(&mut self, id: u32, coords: Position) {
+        let Some(player) = self.players.get_mut(&id) else {
+            return;
+        };
+        let old_coords = player.coords;
+        player.dead = false;
+        player.hp = PLAYER_HP;
+        player.coords = coords;
+        player.reset_loadout(self.now);
+
+        self.vacate(id, old_coords);
+        self.arena.cell_mut(coords).occupant = Some(id);
+        self.spatial.set(id, coords);
+    }
+
+    /// Swaps in a new arena, every player has to be respawned on it. Projectiles in flight are
+    /// dropped with the old one
+    pub fn replace_arena(&mut self, arena: Arena) {
+        self.spatial = SpatialHash::new(arena.height, arena.width);
+        self.projectiles.clear();
+        self.arena = arena;
+    }
+
+    /// Puts a living player straight onto any free cell, without picking up what lies there
+    pub fn teleport(&mut self, id: u32, to: Position) -> Result<Event, RejectReason> {
+        let player = self.players.get_mut(&id).ok_or(RejectReason::PlayerGone)?;
+        if player.dead {
+            return Err(RejectReason::PlayerDead);
+        }
+        let cell = self.arena.get_mut(to).ok_or(RejectReason::OutsideMap)?;
+        if cell.block.is_blocking() {
+            return Err(RejectReason::Wall);
+        }
+        if cell.occupant.is_some() {
+            return Err(RejectReason::Occupied);
+        }
+
+        let from = player.coords;
+        cell.occupant = Some(id);
+        player.coords = to;
+        self.vacate(id, from);
+        self.spatial.set(id, to);
+
+        Ok(Event::Moved { id, from, to })
+    }
+
+    /// Sets the hp of a living player, kept between 1 and PLAYER_HP. Returns the new hp
+    pub fn set_hp(&mut self, id: u32, hp: u8) -> Result<u8, RejectReason> {
+        let player = self.players.get_mut(&id).ok_or(RejectReason::PlayerGone)?;
+        if player.dead {
+            return Err(RejectReason::PlayerDead);
+        }
+        player.hp = hp.clamp(1, PLAYER_HP);
+
+        Ok(player.hp)
+    }
+
+    /// Takes the player off the cell if it still stands there
+    fn vacate(&mut self, id: u32, coords: Position) {
+        if let Some(cell) = self
+            .arena
+            .get_mut(coords)
+            .filter(|cell| cell.occupant == Some(id))
+        {
+            cell.occupant = None;
+        }
+    }
+
+    /// Lets time pass, reloads which are done by `now` finish and projectiles fly on
+    pub fn tick(&mut self, now: Instant) -> Vec<Event> {
+        self.now = max(self.now, now);
+
+        let mut events = vec![];
+        for player in self.players.values_mut() {
+            let current = player.current_weapon;
+            let mut current_reloaded = false;
+            for (index, weapon) in player.weapons.iter_mut().enumerate() {
+                if weapon.finish_reload(self.now) && index == current {
+                    current_reloaded = true;
+                }
+            }
+
+            if current_reloaded {
+                events.push(Event::WeaponChanged(player.id));
+            }
+        }
+
+        // one step at a time in the order they are due, a fast projectile overtakes a slow one
+        // between two ticks the same way it would on a faster clock
+        while let Some(id) = self
+            .projectiles
+            .values()
+            .filter(|p| p.next_step_at <= self.now)
+            .min_by_key(|p| (p.next_step_at, p.id))
+            .map(|p| p.id)
+        {
+            let Some(mut projectile) = self.projectiles.remove(&id) else {
+                break;
+            };
+            if self.advance_projectile(&mut projectile, &mut events) {
+                projectile.next_step_at += projectile.kind.spec().projectile_step;
+                self.projectiles.insert(id, projectile);
+            }
+        }
+
+        events
+    }
+
+    pub fn apply(&mut self, action: Action) -> Vec<Event> {
+        let mut events = vec![];
+        let result = match action {
+            Action::Move(id, direction) => self.do_move(id, direction, &mut events),
+            Action::Shoot(id, direction) => self.do_shoot(id, direction, &mut events),
+            Action::SwitchWeapon(id, kind) => self.switch_weapon(id, kind, &mut events),
+            Action::Reload(id) => self.reload(id, &mut events),
+            Action::UseHealthPack(id) => self.use_health_pack(id, &mut events),
+        };
+        if let Err(reason) = result {
+            events.push(Event::Rejected { action, reason });
+        }
+
+        events
+    }
+
+    fn alive_player_mut(&mut self, id: u32) -> Result<&mut Player, RejectReason> {
+        match self.players.get_mut(&id) {
+            Some(player) if player.dead => Err(RejectReason::PlayerDead),
+            Some(player) => Ok(player),
+            None => Err(RejectReason::PlayerGone),
+        }
+    }
+
+    fn do_move(
+        &mut self,
+        id: u32,
+        direction: Direction,
+        events: &mut Vec<Event>,
+    ) -> Result<(), RejectReason> {
+        let now = self.now;
+        let (height, width) = (self.arena.height, self.arena.width);
+        let player = self.players.get_mut(&id).ok_or(RejectReason::PlayerGone)?;
+        if player.dead {
+            return Err(RejectReason::PlayerDead);
+        }
+        if now < player.next_move_at {
+            return Err(RejectReason::MovingTooFast);
+        }
+
+        let from = player.coords;
+        let to = from
+            .neighbour(direction, height, width)
+            .ok_or(RejectReason::OutsideMap)?;
+        let cell = self.arena.cell(to);
+        if cell.block.is_blocking() {
+            return Err(RejectReason::Wall);
+        }
+        if self.arena.cuts_corner(from, to) {
+            return Err(RejectReason::SqueezeBetweenWalls);
+        }
+        if cell.occupant.is_some() {
+            return Err(RejectReason::Occupied);
+        }
+
+        self.arena.cell_mut(from).occupant = None;
+        let cell = self.arena.cell_mut(to);
+        cell.occupant = Some(id);
+        player.coords = to;
+        player.next_move_at = now + MOVE_COOLDOWN - MOVE_COOLDOWN_TOLERANCE;
+        self.spatial.set(id, to);
+        events.push(Event::Moved { id, from, to });
+
+        if let Some(item) = cell.item {
+            if player.pick_up(item, now) {
+                cell.item = None;
+                events.push(Event::InventoryChanged(id));
+                events.push(Event::WeaponChanged(id));
+                events.push(Event::CellChanged(to));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn do_shoot(
+        &mut self,
+        id: u32,
+        direction: Direction,
+        events: &mut Vec<Event>,
+    ) -> Result<(), RejectReason> {
+        let now = self.now;
+        let shooter = self.alive_player_mut(id)?;
+        let current = shooter.current_weapon;
+        let fired = shooter.weapons[current].fire(now);
+        let (origin, team, spec) = (
+            shooter.coords,
+            shooter.team,
+            shooter.current_weapon().kind.spec(),
+        );
+        // ammo or the reload state changed either way
+        events.push(Event::WeaponChanged(id));
+        fired?;
+
+        let spread = spec.spread as i16;
+        for lane in -spread..=spread {
+            if shot_cell(&self.arena, origin, direction, 1, lane).is_none() {
+                continue;
+            }
+
+            let mut projectile = Projectile {
+                id: self.projectile_counter,
+                owner: id,
+                team,
+                kind: spec.kind,
+                direction,
+                origin,
+                lane,
+                travelled: 0,
+                pierced: 0,
+                coords: origin,
+                next_step_at: now + spec.projectile_step,
+            };
+            self.projectile_counter = self.projectile_counter.wrapping_add(1);
+            events.push(Event::ProjectileSpawned(projectile.visible()));
+
+            // the first cell is reached as the trigger is pulled, point blank shots cannot be
+            // dodged
+            if self.advance_projectile(&mut projectile, events) {
+                self.projectiles.insert(projectile.id, projectile);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the projectile one cell on, returns false once it has stopped
+    fn advance_projectile(&mut self, projectile: &mut Projectile, events: &mut Vec<Event>) -> bool {
+        let spec = projectile.kind.spec();
+        // nobody would be credited for the hits of a player who has left
+        if !self.players.contains_key(&projectile.owner) {
+            events.push(Event::ProjectileHit {
+                id: projectile.id,
+                coords: projectile.coords,
+            });
+            return false;
+        }
+
+        let next = shot_cell(
+            &self.arena,
+            projectile.origin,
+            projectile.direction,
+            projectile.travelled.saturating_add(1),
+            projectile.lane,
+        )
+        .filter(|&cell| {
+            !self.arena.cell(cell).block.is_blocking()
+                && !self.arena.cuts_corner(projectile.coords, cell)
+        });
+        let Some(cell) = next else {
+            self.detonate(projectile, None, events);
+            return false;
+        };
+
+        let from = projectile.coords;
+        projectile.coords = cell;
+        projectile.travelled += 1;
+        events.push(Event::ProjectileMoved {
+            projectile: projectile.visible(),
+            from,
+        });
+
+        if let Some(enemy) = self.enemy_at(cell, projectile.owner, projectile.team) {
+            if spec.blast_radius > 0 {
+                self.detonate(projectile, Some(enemy), events);
+                return false;
+            }
+
+            self.damage(enemy, spec.damage, projectile, events);
+            projectile.pierced += 1;
+            if projectile.pierced >= spec.pierce {
+                self.detonate(projectile, Some(enemy), events);
+                return false;
+            }
+        }
+
+        if projectile.travelled >= spec.range {
+            self.detonate(projectile, None, events);
+            return false;
+        }
+
+        true
+    }
+
+    /// Ends the flight where the projectile is, hurting everyone around if it explodes. `hit` is
+    /// the player it stopped on
+    fn detonate(&mut self, projectile: &Projectile, hit: Option<u32>, events: &mut Vec<Event>) {
+        let spec = projectile.kind.spec();
+        // a projectile which never left the muzzle has nothing to go off on
+        if spec.blast_radius > 0 && projectile.travelled > 0 {
+            let (center, arena) = (projectile.coords, &self.arena);
+            let radius = spec.blast_radius as i16;
+            let enemies = (-radius..=radius)
+                .flat_map(|rows| (-radius..=radius).map(move |cols| Vector::new(rows, cols)))
+                .filter_map(|vector| center.offset(vector, arena.height, arena.width))
+                .filter(|&cell| utils::is_inside_circle(center, spec.blast_radius, cell))
+                .filter_map(|cell| self.enemy_at(cell, projectile.owner, projectile.team))
+                .collect::<Vec<_>>();
+            for enemy in enemies {
+                self.damage(enemy, spec.damage, projectile, events);
+            }
+        }
+
+        events.push(Event::ProjectileHit {
+            id: projectile.id,
+            coords: projectile.coords,
+        });
+
+        let first = shot_cell(
+            &self.arena,
+            projectile.origin,
+            projectile.direction,
+            1,
+            projectile.lane,
+        );
+        if let (Some(origin), true) = (first, projectile.travelled > 0) {
+            events.push(Event::ShotFired {
+                shooter: projectile.owner,
+                origin,
+                direction: projectile.direction,
+                range: projectile.travelled,
+                hit,
+            });
+        }
+    }
+
+    /// Player standing on the cell whom the projectile can hurt, never its owner nor, with
+    /// teams, one of the owner's teammates
+    fn enemy_at(&self, coords: Position, owner: u32, team: Option<Team>) -> Option<u32> {
+        self.arena.cell(coords).occupant.filter(|&id| {
+            id != owner && (team.is_none() || self.players.get(&id).is_some_and(|p| p.team != team))
+        })
+    }
+
+    /// Armor takes the damage first, the player is taken off the map once the hp run out
+    fn damage(&mut self, id: u32, damage: u8, projectile: &Projectile, events: &mut Vec<Event>) {
+        let Some(enemy) = self.players.get_mut(&id) else {
+            return;
+        };
+        let armor = enemy.armor;
+        let damage = enemy.absorb_damage(damage);
+        enemy.hp = enemy.hp.saturating_sub(damage);
+
+        if enemy.hp == 0 {
+            enemy.dead = true;
+            let coords = enemy.coords;
+            self.vacate(id, coords);
+            events.push(Event::Killed {
+                id,
+                by: projectile.owner,
+            });
+            return;
+        }
+
+        if enemy.armor != armor {
+            events.push(Event::InventoryChanged(id));
+        }
+        events.push(Event::Hit {
+            id,
+            damage,
+            direction: projectile.direction,
+        });
+    }
+
+    fn switch_weapon(
+        &mut self,
+        id: u32,
+        kind: WeaponKind,
+        events: &mut Vec<Event>,
+    ) -> Result<(), RejectReason> {
+        let player = self.alive_player_mut(id)?;
+        let index = player
+            .weapons
+            .iter()
+            .position(|w| w.kind == kind)
+            .ok_or(RejectReason::WeaponNotOwned)?;
+        player.current_weapon = index;
+        events.push(Event::WeaponChanged(id));
+
+        Ok(())
+    }
+
+    fn reload(&mut self, id: u32, events: &mut Vec<Event>) -> Result<(), RejectReason> {
+        let now = self.now;
+        let player = self.alive_player_mut(id)?;
+        let current = player.current_weapon;
+        if player.weapons[current].start_reload(now) {
+            events.push(Event::WeaponChanged(id));
+        }
+
+        Ok(())
+    }
+
+    fn use_health_pack(&mut self, id: u32, events: &mut Vec<Event>) -> Result<(), RejectReason> {
+        let hp = self.alive_player_mut(id)?.use_health_pack()?;
+        events.push(Event::Healed { id, hp });
+        events.push(Event::InventoryChanged(id));
+
+        Ok(())
+    }
+}
+
+/// Cell `distance` steps along `direction`, shifted `lane` cells sideways, if it is on the arena
+pub fn shot_cell(
+    arena: &Arena,
+    origin: Position,
+    direction: Direction,
+    distance: u8,
+    lane: i16,
+) -> Option<Position> {
+    let delta = direction.delta();
+    let vector = delta * distance as i16 + delta.sideways() * lane;
+
+    origin.offset(vector, arena.height, arena.width)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn world(height: usize, width: usize, walls: &[Position]) -> World {
+        let mut coords = vec![vec![Block::Grass; width]; height];
+        for wall in walls {
+            coords[wall.row as usize][wall.col as usize] = Block::WallVertical;
+        }
+
+        World::new(
+            Arena::from_map(&Map {
+                height,
+                width,
+                coords,
+            }),
+            Instant::now(),
+        )
+    }
+
+    fn arm(world: &mut World, id: u32, kind: WeaponKind) {
+        let now = world.now;
+        let player = world.players.get_mut(&id).unwrap();
+        player.weapons.push(Weapon::new(kind, now));
+        player.current_weapon = player.weapons.len() - 1;
+    }
+
+    /// Fires and lets every projectile land, leaves out the projectile events
+    fn shoot(world: &mut World, id: u32, direction: Direction) -> Vec<Event> {
+        let mut events = world.apply(Action::Shoot(id, direction));
+        events.extend(land(world));
+
+        without_projectiles(events)
+    }
+
+    /// Lets time pass until every projectile has landed, leaves out the projectile events
+    fn land(world: &mut World) -> Vec<Event> {
+        let mut events = vec![];
+        while !world.projectiles.is_empty() {
+            let now = world.now + Duration::from_millis(10);
+            events.extend(world.tick(now));
+        }
+
+        without_projectiles(events)
+    }
+
+    fn without_projectiles(events: Vec<Event>) -> Vec<Event> {
+        events
+            .into_iter()
+            .filter(|event| {
+                !matches!(
+                    event,
+                    Event::ProjectileSpawned(_)
+                        | Event::ProjectileMoved { .. }
+                        | Event::ProjectileHit { .. }
+                        | Event::ShotFired { .. }
+                )
+            })
+            .collect()
+    }
+
+    fn rejection(events: &[Event]) -> Option<RejectReason> {
+        events.iter().find_map(|event| match *event {
+            Event::Rejected { reason, .. } => Some(reason),
+            _ => None,
+        })
+    }
+
+    fn sorted(ids: impl Iterator<Item = u32>) -> Vec<u32> {
+        let mut ids = ids.collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn the_spatial_hash_follows_players_across_buckets() {
+        let mut spatial = SpatialHash::new(20, 20);
+        let far = Position::new(
+            SPATIAL_BUCKET_SIZE as u16 + 4,
+            SPATIAL_BUCKET_SIZE as u16 + 4,
+        );
+        spatial.set(0, Position::new(1, 1));
+        spatial.set(1, Position::new(2, 2));
+        spatial.set(2, far);
+
+        assert_eq!(sorted(spatial.within(Position::new(1, 1), 3)), [0, 1]);
+        assert_eq!(sorted(spatial.within(far, 3)), [2]);
+
+        // one step over the bucket border
+        let border = SPATIAL_BUCKET_SIZE as u16;
+        spatial.set(0, Position::new(border, border));
+        assert_eq!(
+            spatial.bucket(Position::new(border, border)),
+            spatial.cols + 1
+        );
+        assert_eq!(sorted(spatial.within(Position::new(1, 1), 3)), [1]);
+        assert_eq!(
+            sorted(spatial.within(Position::new(border - 1, border - 1), 2)),
+            [0]
+        );
+        assert_eq!(sorted(spatial.within(far, 10)), [0, 2]);
+        assert_eq!(spatial.buckets.iter().map(Vec::len).sum::<usize>(), 3);
+    }
+
+    #[test]
+    fn removed_players_leave_the_spatial_hash() {
+        let mut spatial = SpatialHash::new(20, 20);
+        spatial.set(0, Position::new(3, 3));
+        spatial.set(1, Position::new(3, 4));
+
+        spatial.remove(0);
+        assert_eq!(sorted(spatial.within(Position::new(3, 3), 2)), [1]);
+        // removing twice or someone never added changes nothing
+        spatial.remove(0);
+        spatial.remove(7);
+        assert_eq!(sorted(spatial.within(Position::new(3, 3), 2)), [1]);
+        assert!(!spatial.positions.contains_key(&0));
+    }
+
+    #[test]
+    fn walls_block_the_line_of_sight() {
+        let world = world(5, 5, &[Position::new(2, 2)]);
+        let arena = &world.arena;
+
+        assert!(!arena.line_of_sight(Position::new(2, 0), Position::new(2, 4)));
+        assert!(!arena.line_of_sight(Position::new(0, 0), Position::new(4, 4)));
+        assert!(!arena.line_of_sight(Position::new(4, 4), Position::new(0, 0)));
+        assert!(arena.line_of_sight(Position::new(1, 0), Position::new(1, 4)));
+        assert!(arena.line_of_sight(Position::new(0, 0), Position::new(4, 1)));
+        assert!(arena.line_of_sight(Position::new(2, 1), Position::new(2, 1)));
+    }
+
+    #[test]
+    fn spawns_fall_back_to_searching_the_half_and_give_up_when_it_is_full() {
+        let mut world = world(4, 4, &[Position::new(0, 2)]);
+        for row in 0..4 {
+            for col in 0..2 {
+                if Position::new(row, col) != Position::new(3, 1) {
+                    world.arena.cell_mut(Position::new(row, col)).occupant = Some(0);
+                }
+            }
+        }
+
+        for _ in 0..10 {
+            assert_eq!(
+                world.arena.random_free_coords(Some(Team::Red)),
+                Some(Position::new(3, 1))
+            );
+        }
+        world.arena.cell_mut(Position::new(3, 1)).occupant = Some(0);
+        assert_eq!(world.arena.random_free_coords(Some(Team::Red)), None);
+        for _ in 0..10 {
+            let coords = world.arena.random_free_coords(Some(Team::Blue)).unwrap();
+            assert!(coords.col >= 2 && coords != Position::new(0, 2));
+        }
+    }
+
+    #[test]
+    fn weapons_cool_down_between_shots_and_reload_from_the_reserve() {
+        let spec = WeaponKind::Shotgun.spec();
+        let now = Instant::now();
+        let mut shotgun = Weapon::new(WeaponKind::Shotgun, now);
+
+        assert!(shotgun.fire(now).is_ok());
+        assert!(shotgun.fire(now).is_err());
+        let now = now + spec.cooldown;
+        assert!(shotgun.fire(now).is_ok());
+
+        // the empty magazine starts the reload right away
+        assert_eq!(shotgun.ammo, 0);
+        assert!(shotgun.reloading);
+        assert!(shotgun.fire(now + spec.reload).is_err());
+        assert!(!shotgun.finish_reload(now));
+        assert!(shotgun.finish_reload(now + spec.reload));
+        assert_eq!(shotgun.ammo, spec.magazine);
+        assert_eq!(shotgun.reserve, spec.max_reserve.unwrap() - spec.magazine);
+
+        // a full magazine is not reloaded
+        assert!(!shotgun.start_reload(now));
+    }
+
+    #[test]
+    fn only_an_endless_reserve_reloads_forever() {
+        let now = Instant::now();
+        let mut grenade = Weapon::new(WeaponKind::Grenade, now);
+        let mut pistol = Weapon::new(WeaponKind::Pistol, now);
+        grenade.ammo = 0;
+        grenade.reserve = 0;
+        assert!(!grenade.start_reload(now));
+        assert_eq!(grenade.fire(now), Err(RejectReason::OutOfAmmo));
+
+        pistol.ammo = 0;
+        assert!(pistol.start_reload(now));
+        assert!(pistol.finish_reload(now + WeaponKind::Pistol.spec().reload));
+        assert_eq!(pistol.ammo, WeaponKind::Pistol.spec().magazine);
+    }
+
+    #[test]
+    fn items_are_only_picked_up_while_they_are_of_use() {
+        let now = Instant::now();
+        let mut player = Player::new(0, "collector".to_string(), None, Position::new(0, 0), now);
+
+        for _ in 0..MAX_HEALTH_PACKS {
+            assert!(player.pick_up(ItemKind::HealthPack, now));
+        }
+        assert!(!player.pick_up(ItemKind::HealthPack, now));
+
+        assert!(player.pick_up(ItemKind::Armor, now));
+        assert!(player.pick_up(ItemKind::Armor, now));
+        assert!(!player.pick_up(ItemKind::Armor, now));
+        assert_eq!(player.armor, MAX_ARMOR);
+        assert_eq!(player.absorb_damage(3), 0);
+        assert_eq!(player.armor, MAX_ARMOR - 3);
+
+        // a new weapon comes with a full reserve, so a second one is of no use
+        assert!(player.pick_up(ItemKind::Weapon(WeaponKind::Shotgun), now));
+        assert!(!player.pick_up(ItemKind::Weapon(WeaponKind::Shotgun), now));
+        assert!(!player.pick_up(ItemKind::Ammo, now));
+        player.weapons.last_mut().unwrap().reserve = 0;
+        assert!(player.pick_up(ItemKind::Ammo, now));
+    }
+
+    #[test]
+    fn moving_has_a_cooldown() {
+        let mut world = world(1, 3, &[]);
+        world.add_player(0, "runner".to_string(), None, Position::new(0, 0));
+
+        assert!(rejection(&world.apply(Action::Move(0, Direction::Right))).is_none());
+        assert_eq!(
+            rejection(&world.apply(Action::Move(0, Direction::Right))),
+            Some(RejectReason::MovingTooFast)
+        );
+
+        world.tick(world.now + MOVE_COOLDOWN);
+        assert!(rejection(&world.apply(Action::Move(0, Direction::Right))).is_none());
+        assert_eq!(world.player(0).unwrap().coords, Position::new(0, 2));
+    }
+
+    #[test]
+    fn a_step_arriving_a_little_early_is_accepted() {
+        let mut world = world(1, 3, &[]);
+        world.add_player(0, "runner".to_string(), None, Position::new(0, 0));
+        assert!(rejection(&world.apply(Action::Move(0, Direction::Right))).is_none());
+
+        world.tick(world.now + MOVE_COOLDOWN - MOVE_COOLDOWN_TOLERANCE - Duration::from_millis(1));
+        assert_eq!(
+            rejection(&world.apply(Action::Move(0, Direction::Right))),
+            Some(RejectReason::MovingTooFast)
+        );
+
+        world.tick(world.now + Duration::from_millis(1));
+        assert!(rejection(&world.apply(Action::Move(0, Direction::Right))).is_none());
+        assert_eq!(world.player(0).unwrap().coords, Position::new(0, 2));
+    }
+
+    #[test]
+    fn dead_players_can_not_act() {
+        let mut world = world(1, 3, &[]);
+        world.add_player(0, "ghost".to_string(), None, Position::new(0, 0));
+        world.players.get_mut(&0).unwrap().dead = true;
+
+        for action in [
+            Action::Move(0, Direction::Right),
+            Action::Shoot(0, Direction::Right),
+            Action::SwitchWeapon(0, WeaponKind::Pistol),
+            Action::Reload(0),
+            Action::UseHealthPack(0),
+        ] {
+            assert_eq!(
+                rejection(&world.apply(action)),
+                Some(RejectReason::PlayerDead)
+            );
+        }
+    }
+
+    #[test]
+    fn teleporting_needs_a_free_cell() {
+        let wall = Position::new(0, 2);
+        let mut world = world(3, 3, &[wall]);
+        world.add_player(0, "teleported".to_string(), None, Position::new(0, 0));
+        world.add_player(1, "in_the_way".to_string(), None, Position::new(1, 1));
+
+        assert_eq!(world.teleport(0, wall).unwrap_err(), RejectReason::Wall);
+        assert_eq!(
+            world.teleport(0, Position::new(1, 1)).unwrap_err(),
+            RejectReason::Occupied
+        );
+        assert_eq!(
+            world.teleport(0, Position::new(3, 0)).unwrap_err(),
+            RejectReason::OutsideMap
+        );
+
+        let to = Position::new(2, 2);
+        assert!(matches!(
+            world.teleport(0, to),
+            Ok(Event::Moved { id: 0, .. })
+        ));
+        assert_eq!(world.player(0).unwrap().coords, to);
+        assert_eq!(world.arena.cell(to).occupant, Some(0));
+        assert_eq!(world.arena.cell(Position::new(0, 0)).occupant, None);
+    }
+
+    #[test]
+    fn moving_off_the_edge_is_rejected() {
+        let mut world = world(3, 3, &[]);
+        world.add_player(0, "top_left".to_string(), None, Position::new(0, 0));
+        world.add_player(1, "bottom_right".to_string(), None, Position::new(2, 2));
+
+        let cases = [
+            Action::Move(0, Direction::Up),
+            Action::Move(0, Direction::Left),
+            Action::Move(1, Direction::Down),
+            Action::Move(1, Direction::Right),
+        ];
+        for action in cases {
+            assert_eq!(
+                rejection(&world.apply(action)),
+                Some(RejectReason::OutsideMap)
+            );
+        }
+
+        assert_eq!(world.player(0).unwrap().coords, Position::new(0, 0));
+        assert_eq!(world.player(1).unwrap().coords, Position::new(2, 2));
+        assert_eq!(world.arena.cell(Position::new(0, 0)).occupant(), Some(0));
+        assert_eq!(world.arena.cell(Position::new(2, 2)).occupant(), Some(1));
+    }
+
+    #[test]
+    fn moving_into_walls_and_players_is_rejected() {
+        let mut world = world(3, 3, &[Position::new(1, 0)]);
+        world.add_player(0, "mover".to_string(), None, Position::new(0, 0));
+        world.add_player(1, "blocker".to_string(), None, Position::new(0, 1));
+
+        let events = world.apply(Action::Move(0, Direction::Down));
+        assert_eq!(rejection(&events), Some(RejectReason::Wall));
+        let events = world.apply(Action::Move(0, Direction::Right));
+        assert_eq!(rejection(&events), Some(RejectReason::Occupied));
+
+        let events = world.apply(Action::Move(1, Direction::Down));
+        assert_eq!(
+            events,
+            vec![Event::Moved {
+                id: 1,
+                from: Position::new(0, 1),
+                to: Position::new(1, 1),
+            }]
+        );
+        assert_eq!(world.arena.cell(Position::new(0, 1)).occupant(), None);
+        assert_eq!(world.arena.cell(Position::new(1, 1)).occupant(), Some(1));
+    }
+
+    #[test]
+    fn shots_stop_at_walls() {
+        let mut world = world(1, 4, &[Position::new(0, 2)]);
+        world.add_player(0, "shooter".to_string(), None, Position::new(0, 0));
+        world.add_player(1, "behind".to_string(), None, Position::new(0, 3));
+        arm(&mut world, 0, WeaponKind::Rifle);
+
+        let events = shoot(&mut world, 0, Direction::Right);
+        assert_eq!(events, vec![Event::WeaponChanged(0)]);
+        assert_eq!(world.player(1).unwrap().hp, PLAYER_HP);
+    }
+
+    #[test]
+    fn diagonal_steps_do_not_squeeze_between_walls() {
+        let mut world = world(3, 3, &[Position::new(0, 1), Position::new(1, 0)]);
+        world.add_player(0, "walker".to_string(), None, Position::new(0, 0));
+        world.add_player(1, "other".to_string(), None, Position::new(2, 2));
+
+        let events = world.apply(Action::Move(0, Direction::DownRight));
+        assert_eq!(rejection(&events), Some(RejectReason::SqueezeBetweenWalls));
+
+        let events = world.apply(Action::Move(1, Direction::UpLeft));
+        assert_eq!(
+            events,
+            vec![Event::Moved {
+                id: 1,
+                from: Position::new(2, 2),
+                to: Position::new(1, 1),
+            }]
+        );
+    }
+
+    #[test]
+    fn shooting_into_the_boundary_hits_nothing() {
+        let mut world = world(3, 3, &[]);
+        world.add_player(0, "shooter".to_string(), None, Position::new(0, 0));
+        world.add_player(1, "target".to_string(), None, Position::new(2, 2));
+
+        let events = shoot(&mut world, 0, Direction::Up);
+        assert_eq!(events, vec![Event::WeaponChanged(0)]);
+        let pistol = WeaponKind::Pistol.spec();
+        assert_eq!(
+            world.player(0).unwrap().current_weapon().ammo,
+            pistol.magazine - 1
+        );
+
+        // the lane ends right at the edge, so there is nothing for the blast to go off on
+        arm(&mut world, 0, WeaponKind::Grenade);
+        let events = shoot(&mut world, 0, Direction::Left);
+        assert_eq!(events, vec![Event::WeaponChanged(0)]);
+        assert_eq!(world.player(1).unwrap().hp, PLAYER_HP);
+    }
+
+    #[test]
+    fn shotgun_lanes_off_the_map_are_skipped() {
+        let mut world = world(3, 5, &[]);
+        world.add_player(0, "shooter".to_string(), None, Position::new(0, 0));
+        world.add_player(1, "target".to_string(), None, Position::new(1, 2));
+        arm(&mut world, 0, WeaponKind::Shotgun);
+
+        let events = shoot(&mut world, 0, Direction::Right);
+        let damage = WeaponKind::Shotgun.spec().damage;
+        assert_eq!(
+            events,
+            vec![
+                Event::WeaponChanged(0),
+                Event::Hit {
+                    id: 1,
+                    damage,
+                    direction: Direction::Right,
+                },
+            ]
+        );
+        assert_eq!(world.player(1).unwrap().hp, PLAYER_HP - damage);
+    }
+
+    #[test]
+    fn diagonal_shots_stop_at_wall_corners() {
+        let mut world = world(4, 4, &[Position::new(1, 2), Position::new(2, 1)]);
+        world.add_player(0, "shooter".to_string(), None, Position::new(0, 0));
+        world.add_player(1, "in_the_open".to_string(), None, Position::new(1, 1));
+        world.add_player(
+            2,
+            "behind_the_corner".to_string(),
+            None,
+            Position::new(3, 3),
+        );
+        arm(&mut world, 0, WeaponKind::Rifle);
+
+        let events = shoot(&mut world, 0, Direction::DownRight);
+        let damage = WeaponKind::Rifle.spec().damage;
+        assert_eq!(
+            events,
+            vec![
+                Event::WeaponChanged(0),
+                Event::Hit {
+                    id: 1,
+                    damage,
+                    direction: Direction::DownRight,
+                },
+            ]
+        );
+        assert_eq!(world.player(2).unwrap().hp, PLAYER_HP);
+    }
+
+    #[test]
+    fn piercing_shot_kills_everyone_in_the_lane() {
+        let mut world = world(1, 6, &[]);
+        world.add_player(0, "shooter".to_string(), None, Position::new(0, 0));
+        world.add_player(1, "first".to_string(), None, Position::new(0, 1));
+        world.add_player(2, "second".to_string(), None, Position::new(0, 3));
+        arm(&mut world, 0, WeaponKind::Rifle);
+        for id in [1, 2] {
+            world.players.get_mut(&id).unwrap().hp = 1;
+        }
+
+        let events = shoot(&mut world, 0, Direction::Right);
+        assert_eq!(
+            events,
+            vec![
+                Event::WeaponChanged(0),
+                Event::Killed { id: 1, by: 0 },
+                Event::Killed { id: 2, by: 0 },
+            ]
+        );
+        for (id, coords) in [(1, Position::new(0, 1)), (2, Position::new(0, 3))] {
+            assert!(world.player(id).unwrap().dead);
+            assert_eq!(world.arena.cell(coords).occupant(), None);
+        }
+    }
+
+    #[test]
+    fn blast_kills_everyone_around_the_impact() {
+        let mut world = world(5, 7, &[]);
+        world.add_player(0, "shooter".to_string(), None, Position::new(2, 0));
+        world.add_player(1, "hit".to_string(), None, Position::new(2, 3));
+        world.add_player(2, "next_to_it".to_string(), None, Position::new(3, 3));
+        world.add_player(3, "too_far".to_string(), None, Position::new(2, 6));
+        arm(&mut world, 0, WeaponKind::Grenade);
+        let damage = WeaponKind::Grenade.spec().damage;
+        for id in [1, 2, 3] {
+            world.players.get_mut(&id).unwrap().hp = damage;
+        }
+
+        let events = shoot(&mut world, 0, Direction::Right);
+        assert_eq!(
+            events,
+            vec![
+                Event::WeaponChanged(0),
+                Event::Killed { id: 1, by: 0 },
+                Event::Killed { id: 2, by: 0 },
+            ]
+        );
+        assert!(!world.player(3).unwrap().dead);
+    }
+
+    #[test]
+    fn the_first_of_two_point_blank_shots_wins() {
+        let mut world = world(1, 2, &[]);
+        world.add_player(0, "quick".to_string(), None, Position::new(0, 0));
+        world.add_player(1, "slow".to_string(), None, Position::new(0, 1));
+        for id in [0, 1] {
+            world.players.get_mut(&id).unwrap().hp = 1;
+        }
+
+        let first = world.apply(Action::Shoot(0, Direction::Right));
+        let second = world.apply(Action::Shoot(1, Direction::Left));
+
+        assert!(first.contains(&Event::Killed { id: 1, by: 0 }));
+        assert_eq!(rejection(&second), Some(RejectReason::PlayerDead));
+        assert!(!world.player(0).unwrap().dead);
+        assert_eq!(world.player(0).unwrap().hp, 1);
+    }
+
+    #[test]
+    fn teammates_are_shot_through() {
+        let mut world = world(1, 4, &[]);
+        world.add_player(
+            0,
+            "shooter".to_string(),
+            Some(Team::Red),
+            Position::new(0, 0),
+        );
+        world.add_player(
+            1,
+            "friend".to_string(),
+            Some(Team::Red),
+            Position::new(0, 1),
+        );
+        world.add_player(
+            2,
+            "enemy".to_string(),
+            Some(Team::Blue),
+            Position::new(0, 2),
+        );
+
+        let events = shoot(&mut world, 0, Direction::Right);
+        assert_eq!(
+            events,
+            vec![
+                Event::WeaponChanged(0),
+                Event::Hit {
+                    id: 2,
+                    damage: 1,
+                    direction: Direction::Right,
+                },
+            ]
+        );
+        assert_eq!(world.player(1).unwrap().hp, PLAYER_HP);
+    }
+
+    #[test]
+    fn projectiles_take_time_to_reach_the_target() {
+        let mut world = world(1, 5, &[]);
+        world.add_player(0, "shooter".to_string(), None, Position::new(0, 0));
+        world.add_player(1, "target".to_string(), None, Position::new(0, 4));
+        let step = WeaponKind::Pistol.spec().projectile_step;
+        let fired_at = world.now;
+
+        let events = world.apply(Action::Shoot(0, Direction::Right));
+        assert!(events.contains(&Event::ProjectileMoved {
+            projectile: protocol::Projectile {
+                id: 0,
+                kind: WeaponKind::Pistol,
+                direction: Direction::Right,
+                coords: Position::new(0, 1),
+            },
+            from: Position::new(0, 0),
+        }));
+
+        let events = world.tick(fired_at + step * 2);
+        assert_eq!(
+            world.projectiles().next().unwrap().coords,
+            Position::new(0, 3)
+        );
+        assert!(!events.iter().any(|e| matches!(e, Event::Hit { .. })));
+
+        let events = world.tick(fired_at + step * 3);
+        assert!(events.contains(&Event::Hit {
+            id: 1,
+            damage: 1,
+            direction: Direction::Right,
+        }));
+        assert!(events.contains(&Event::ProjectileHit {
+            id: 0,
+            coords: Position::new(0, 4),
+        }));
+        assert_eq!(world.projectiles().count(), 0);
+    }
+
+    #[test]
+    fn stepping_out_of_the_lane_dodges_a_projectile() {
+        let mut world = world(2, 6, &[]);
+        world.add_player(0, "shooter".to_string(), None, Position::new(0, 0));
+        world.add_player(1, "dodger".to_string(), None, Position::new(0, 5));
+
+        world.apply(Action::Shoot(0, Direction::Right));
+        world.apply(Action::Move(1, Direction::Down));
+
+        assert_eq!(land(&mut world), vec![]);
+        assert_eq!(world.player(1).unwrap().hp, PLAYER_HP);
+    }
+
+    #[test]
+    fn projectiles_of_players_who_left_fizzle_out() {
+        let mut world = world(1, 5, &[]);
+        world.add_player(0, "leaver".to_string(), None, Position::new(0, 0));
+        world.add_player(1, "target".to_string(), None, Position::new(0, 4));
+
+        world.apply(Action::Shoot(0, Direction::Right));
+        world.remove_player(0);
+        let events = world.tick(world.now + WeaponKind::Pistol.spec().projectile_step * 3);
+
+        assert_eq!(
+            events,
+            vec![Event::ProjectileHit {
+                id: 0,
+                coords: Position::new(0, 1),
+            }]
+        );
+        assert_eq!(world.player(1).unwrap().hp, PLAYER_HP);
+    }
+
+    #[test]
+    fn shot_fired_tells_the_path_and_who_was_hit() {
+        let mut world = world(3, 6, &[Position::new(2, 2)]);
+        world.add_player(0, "shooter".to_string(), None, Position::new(1, 0));
+        world.add_player(1, "target".to_string(), None, Position::new(1, 3));
+        arm(&mut world, 0, WeaponKind::Shotgun);
+
+        let mut events = world.apply(Action::Shoot(0, Direction::Right));
+        while world.projectiles().next().is_some() {
+            let now = world.now + Duration::from_millis(10);
+            events.extend(world.tick(now));
+        }
+        let shots = events
+            .into_iter()
+            .filter(|event| matches!(event, Event::ShotFired { .. }))
+            .collect::<Vec<_>>();
+
+        let shot = |row, range, hit| Event::ShotFired {
+            shooter: 0,
+            origin: Position::new(row, 1),
+            direction: Direction::Right,
+            range,
+            hit,
+        };
+        // the lower lane runs into the wall, the middle one stops on the target
+        assert_eq!(shots.len(), 3);
+        assert!(shots.contains(&shot(1, 3, Some(1))));
+        assert!(shots.contains(&shot(0, 3, None)));
+        assert!(shots.contains(&shot(2, 1, None)));
+    }
+}
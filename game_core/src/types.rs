@@ -1,16 +1,173 @@
+use std::{
+    ops::{Add, Mul},
+    str::FromStr,
+};
+
 use proto_dryb::*;
 use proto_dryb_derive::{Deserialize, Serialize};
 
-pub type Coords = (u16, u16);
+use crate::weapons::WeaponKind;
+
+/// Cell on a map. Rows grow downwards and are bounded by the map height, columns grow to the
+/// right and are bounded by its width
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Position {
+    pub row: u16,
+    pub col: u16,
+}
+
+impl Position {
+    pub const fn new(row: u16, col: u16) -> Self {
+        Self { row, col }
+    }
+
+    /// Whether it lies on a map `height` rows high and `width` columns wide
+    pub fn is_inside(self, height: usize, width: usize) -> bool {
+        (self.row as usize) < height && (self.col as usize) < width
+    }
+
+    /// Position moved by `vector`, None if that falls off a `height` x `width` map
+    pub fn offset(self, vector: Vector, height: usize, width: usize) -> Option<Position> {
+        let row = self.row.checked_add_signed(vector.rows)?;
+        let col = self.col.checked_add_signed(vector.cols)?;
+
+        Some(Position::new(row, col)).filter(|p| p.is_inside(height, width))
+    }
+
+    /// Cell one step along `direction`, None if that falls off a `height` x `width` map
+    pub fn neighbour(self, direction: Direction, height: usize, width: usize) -> Option<Position> {
+        self.offset(direction.delta(), height, width)
+    }
+
+    /// How to get from `self` to `other`
+    pub fn vector_to(self, other: Position) -> Vector {
+        Vector::new(
+            other.row as i16 - self.row as i16,
+            other.col as i16 - self.col as i16,
+        )
+    }
+
+    /// Steps between the two along rows and columns
+    pub fn manhattan_distance(self, other: Position) -> u16 {
+        self.row.abs_diff(other.row) + self.col.abs_diff(other.col)
+    }
+}
+
+/// Difference between two positions, in rows and columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Vector {
+    pub rows: i16,
+    pub cols: i16,
+}
+
+impl Vector {
+    pub const fn new(rows: i16, cols: i16) -> Self {
+        Self { rows, cols }
+    }
+
+    /// The same vector turned a quarter clockwise. Lanes of a shot are spread along it
+    pub fn sideways(self) -> Vector {
+        Vector::new(self.cols, -self.rows)
+    }
+
+    /// Squared length, compared against squared radii
+    pub fn length_squared(self) -> i32 {
+        (self.rows as i32).pow(2) + (self.cols as i32).pow(2)
+    }
+}
+
+impl Add for Vector {
+    type Output = Vector;
+
+    fn add(self, other: Vector) -> Vector {
+        Vector::new(self.rows + other.rows, self.cols + other.cols)
+    }
+}
+
+impl Mul<i16> for Vector {
+    type Output = Vector;
+
+    fn mul(self, factor: i16) -> Vector {
+        Vector::new(self.rows * factor, self.cols * factor)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Up,
+    Right,
+    Down,
+    Left,
+    UpRight,
+    DownRight,
+    DownLeft,
+    UpLeft,
+}
+
+impl Direction {
+    /// Walking cell by cell without cutting corners
+    pub const CARDINAL: [Direction; 4] = [
+        Direction::Up,
+        Direction::Right,
+        Direction::Down,
+        Direction::Left,
+    ];
+    pub const ALL: [Direction; 8] = [
+        Direction::Up,
+        Direction::UpRight,
+        Direction::Right,
+        Direction::DownRight,
+        Direction::Down,
+        Direction::DownLeft,
+        Direction::Left,
+        Direction::UpLeft,
+    ];
+
+    /// One step along the direction
+    pub fn delta(self) -> Vector {
+        match self {
+            Self::Up => Vector::new(-1, 0),
+            Self::Right => Vector::new(0, 1),
+            Self::Down => Vector::new(1, 0),
+            Self::Left => Vector::new(0, -1),
+            Self::UpRight => Vector::new(-1, 1),
+            Self::DownRight => Vector::new(1, 1),
+            Self::DownLeft => Vector::new(1, -1),
+            Self::UpLeft => Vector::new(-1, -1),
+        }
+    }
+
+    pub fn is_diagonal(self) -> bool {
+        let delta = self.delta();
+        delta.rows != 0 && delta.cols != 0
+    }
+}
+
+impl TryFrom<char> for Direction {
+    type Error = ();
+    fn try_from(value: char) -> Result<Self, Self::Error> {
+        match value {
+            'w' | 'k' => Ok(Self::Up),
+            'd' | 'l' => Ok(Self::Right),
+            's' | 'j' => Ok(Self::Down),
+            'a' | 'h' => Ok(Self::Left),
+            'e' | 'u' => Ok(Self::UpRight),
+            'c' | 'n' => Ok(Self::DownRight),
+            'z' | 'b' => Ok(Self::DownLeft),
+            'q' | 'y' => Ok(Self::UpLeft),
+            _ => Err(()),
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct MapCell {
     pub block: Block,
-    pub coords: Coords,
+    pub coords: Position,
 }
 
 impl MapCell {
-    pub fn new(block: Block, coords: Coords) -> Self {
+    pub fn new(block: Block, coords: Position) -> Self {
         Self { block, coords }
     }
 }
@@ -21,7 +178,51 @@ pub struct Map {
     pub coords: Vec<Vec<Block>>,
 }
 
-pub type MoveCoords = (Coords, Vec<MapCell>);
+/// A map drawn as text, one line per row. Grass is `.`, walls are drawn with the box characters
+/// the client shows them with and a space leaves the cell empty
+impl FromStr for Map {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let coords = text
+            .trim_end_matches(['\n', '\r'])
+            .lines()
+            .enumerate()
+            .map(|(row, line)| {
+                line.chars()
+                    .map(|c| match c {
+                        '.' => Ok(Block::Grass),
+                        ' ' => Ok(Block::Void),
+                        '━' => Ok(Block::WallHorizontal),
+                        '┃' => Ok(Block::WallVertical),
+                        '┏' => Ok(Block::WallTopLeft),
+                        '┓' => Ok(Block::WallTopRight),
+                        '┗' => Ok(Block::WallBottomLeft),
+                        '┛' => Ok(Block::WallBottomRight),
+                        _ => Err(format!("Unknown block '{c}' in row {row}")),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let width = coords.first().map_or(0, |row| row.len());
+        if width == 0 {
+            return Err("The map is empty".to_string());
+        }
+        if let Some(row) = coords.iter().position(|cells| cells.len() != width) {
+            return Err(format!(
+                "Row {row} is {} cells wide instead of {width}",
+                coords[row].len()
+            ));
+        }
+
+        Ok(Map {
+            height: coords.len(),
+            width,
+            coords,
+        })
+    }
+}
 
 #[derive(Clone, Copy, Serialize, Deserialize, Debug)]
 pub enum Block {
@@ -36,4 +237,99 @@ pub enum Block {
     WallTopRight,
     WallBottomLeft,
     WallBottomRight,
+
+    Item(ItemKind),
+    Flag(Team),
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Team {
+    Red,
+    Blue,
+}
+
+impl Team {
+    pub const ALL: [Team; 2] = [Team::Red, Team::Blue];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Team::Red => "RED",
+            Team::Blue => "BLUE",
+        }
+    }
+}
+
+impl Block {
+    /// Nobody can walk or shoot through it
+    pub fn is_blocking(self) -> bool {
+        matches!(
+            self,
+            Block::WallHorizontal
+                | Block::WallVertical
+                | Block::WallTopLeft
+                | Block::WallTopRight
+                | Block::WallBottomLeft
+                | Block::WallBottomRight
+        )
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum ItemKind {
+    HealthPack,
+    Ammo,
+    Armor,
+    Weapon(WeaponKind),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_stay_on_the_map() {
+        let corner = Position::new(0, 0);
+        assert_eq!(corner.neighbour(Direction::Up, 3, 4), None);
+        assert_eq!(corner.neighbour(Direction::Left, 3, 4), None);
+        assert_eq!(
+            corner.neighbour(Direction::Down, 3, 4),
+            Some(Position::new(1, 0))
+        );
+
+        // the last row and column are height - 1 and width - 1
+        let far_corner = Position::new(2, 3);
+        assert_eq!(far_corner.neighbour(Direction::Down, 3, 4), None);
+        assert_eq!(far_corner.neighbour(Direction::Right, 3, 4), None);
+        assert_eq!(far_corner.neighbour(Direction::DownLeft, 3, 4), None);
+        assert_eq!(
+            far_corner.neighbour(Direction::UpLeft, 3, 4),
+            Some(Position::new(1, 2))
+        );
+        assert_eq!(far_corner.offset(Vector::new(-2, -3), 3, 4), Some(corner));
+        assert_eq!(corner.offset(Vector::new(i16::MAX, 0), 3, 4), None);
+    }
+
+    #[test]
+    fn maps_are_parsed_row_by_row() {
+        let map = "┏━━┓\n┃..┃\n┗━━┛\n".parse::<Map>().unwrap();
+        assert_eq!((map.height, map.width), (3, 4));
+        assert!(matches!(map.coords[0][0], Block::WallTopLeft));
+        assert!(matches!(map.coords[1][2], Block::Grass));
+        assert!(matches!(map.coords[2][3], Block::WallBottomRight));
+
+        assert!("".parse::<Map>().is_err());
+        assert!("...\n..\n".parse::<Map>().is_err());
+        assert!("..x\n".parse::<Map>().is_err());
+    }
+
+    #[test]
+    fn vectors_follow_rows_then_columns() {
+        let from = Position::new(1, 5);
+        let to = Position::new(4, 2);
+        assert_eq!(from.vector_to(to), Vector::new(3, -3));
+        assert_eq!(from.offset(from.vector_to(to), 10, 10), Some(to));
+        assert_eq!(from.manhattan_distance(to), 6);
+        assert_eq!(Direction::Right.delta().sideways(), Vector::new(1, 0));
+        assert_eq!(Direction::UpRight.delta().sideways(), Vector::new(1, 1));
+    }
 }
@@ -1,26 +1,32 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use rand::{thread_rng, Rng};
 
-use crate::types::{Block, Coords, Map};
+use crate::types::{Block, Map, Position};
 
-pub fn generate_random_coords(max_x: usize, max_y: usize) -> Coords {
+pub fn generate_random_position(height: usize, width: usize) -> Position {
     let mut rng = thread_rng();
 
-    (
-        rng.gen_range(0..max_x) as u16,
-        rng.gen_range(0..max_y) as u16,
+    Position::new(
+        rng.gen_range(0..height) as u16,
+        rng.gen_range(0..width) as u16,
     )
 }
 
-pub fn is_inside_circle(
-    (center_x, center_y): Coords,
-    radius: u8,
-    (other_x, other_y): Coords,
-) -> bool {
-    let diff_sqr =
-        (center_x as i16 - other_x as i16).pow(2) + (center_y as i16 - other_y as i16).pow(2);
-    let radius_sqr = (radius as i16).pow(2);
+/// Random enough that a session can not be taken over by guessing its token
+pub fn generate_session_token() -> u64 {
+    thread_rng().gen()
+}
+
+/// Milliseconds since the unix epoch, the timestamp pings carry
+pub fn timestamp_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
 
-    diff_sqr <= radius_sqr
+pub fn is_inside_circle(center: Position, radius: u8, other: Position) -> bool {
+    center.vector_to(other).length_squared() <= (radius as i32).pow(2)
 }
 
 pub fn generate_map() -> Map {
@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use proto_dryb::*;
+use proto_dryb_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum WeaponKind {
+    #[default]
+    Pistol,
+    Shotgun,
+    Rifle,
+    Grenade,
+}
+
+pub struct WeaponSpec {
+    pub kind: WeaponKind,
+    pub name: &'static str,
+    /// Cells travelled from the shooter
+    pub range: u8,
+    pub damage: u8,
+    /// Players a single lane passes through before stopping
+    pub pierce: u8,
+    /// Extra parallel lanes on each side of the aimed one
+    pub spread: u8,
+    /// Area damage around the first hit or the end of the lane, 0 for none
+    pub blast_radius: u8,
+    /// Time a projectile takes to fly from one cell to the next
+    pub projectile_step: Duration,
+    pub cooldown: Duration,
+    pub magazine: u8,
+    /// None for an endless reserve
+    pub max_reserve: Option<u8>,
+    pub reload: Duration,
+}
+
+pub const WEAPONS: [WeaponSpec; 4] = [
+    WeaponSpec {
+        kind: WeaponKind::Pistol,
+        name: "PISTOL",
+        range: 6,
+        damage: 1,
+        pierce: 1,
+        spread: 0,
+        blast_radius: 0,
+        projectile_step: Duration::from_millis(40),
+        cooldown: Duration::from_millis(300),
+        magazine: 8,
+        max_reserve: None,
+        reload: Duration::from_millis(1200),
+    },
+    WeaponSpec {
+        kind: WeaponKind::Shotgun,
+        name: "SHOTGUN",
+        range: 3,
+        damage: 2,
+        pierce: 1,
+        spread: 1,
+        blast_radius: 0,
+        projectile_step: Duration::from_millis(40),
+        cooldown: Duration::from_millis(900),
+        magazine: 2,
+        max_reserve: Some(16),
+        reload: Duration::from_millis(2000),
+    },
+    WeaponSpec {
+        kind: WeaponKind::Rifle,
+        name: "RIFLE",
+        range: 12,
+        damage: 3,
+        pierce: 3,
+        spread: 0,
+        blast_radius: 0,
+        projectile_step: Duration::from_millis(20),
+        cooldown: Duration::from_millis(1500),
+        magazine: 4,
+        max_reserve: Some(12),
+        reload: Duration::from_millis(2500),
+    },
+    WeaponSpec {
+        kind: WeaponKind::Grenade,
+        name: "GRENADE",
+        range: 7,
+        damage: 4,
+        pierce: 1,
+        spread: 0,
+        blast_radius: 2,
+        projectile_step: Duration::from_millis(80),
+        cooldown: Duration::from_millis(2000),
+        magazine: 1,
+        max_reserve: Some(3),
+        reload: Duration::from_millis(1000),
+    },
+];
+
+impl WeaponKind {
+    pub fn spec(self) -> &'static WeaponSpec {
+        &WEAPONS[self as usize]
+    }
+
+    /// Number keys 1..=4 pick the weapon in catalog order
+    pub fn from_slot(slot: char) -> Option<Self> {
+        let index = slot.to_digit(10)?.checked_sub(1)? as usize;
+        WEAPONS.get(index).map(|spec| spec.kind)
+    }
+}
@@ -24,7 +24,7 @@ where
     T: Serialize,
 {
     fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
-        if buffer.len() < 1 {
+        if buffer.is_empty() {
             return Err(SerializeError::BufferOverflow);
         }
 
@@ -46,7 +46,7 @@ where
     T: Serialize,
 {
     fn serialize(&self, buf: &mut [u8]) -> Result<usize, SerializeError> {
-        if buf.len() < 1 {
+        if buf.is_empty() {
             return Err(SerializeError::BufferOverflow);
         }
 
@@ -86,9 +86,21 @@ impl_serialize_tuple!(0 A, 1 B);
 // TODO add tuples of size 3,4..N when needed
 
 // Primitive implimintations
+impl Serialize for bool {
+    fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
+        if buffer.is_empty() {
+            return Err(SerializeError::BufferOverflow);
+        }
+
+        buffer[0] = *self as u8;
+
+        Ok(1)
+    }
+}
+
 impl Serialize for u8 {
     fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
-        if buffer.len() < 1 {
+        if buffer.is_empty() {
             return Err(SerializeError::BufferOverflow);
         }
 
@@ -100,7 +112,7 @@ impl Serialize for u8 {
 
 impl Serialize for i8 {
     fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
-        if buffer.len() < 1 {
+        if buffer.is_empty() {
             return Err(SerializeError::BufferOverflow);
         }
 
@@ -166,7 +178,58 @@ impl Serialize for i32 {
     }
 }
 
-// TODO u64 i64
+impl Serialize for u64 {
+    fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
+        if buffer.len() < 8 {
+            return Err(SerializeError::BufferOverflow);
+        }
+
+        buffer[0] = (*self >> 56) as u8;
+        buffer[1] = (*self >> 48) as u8;
+        buffer[2] = (*self >> 40) as u8;
+        buffer[3] = (*self >> 32) as u8;
+        buffer[4] = (*self >> 24) as u8;
+        buffer[5] = (*self >> 16) as u8;
+        buffer[6] = (*self >> 8) as u8;
+        buffer[7] = *self as u8;
+
+        Ok(8)
+    }
+}
+
+impl Serialize for i64 {
+    fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
+        if buffer.len() < 8 {
+            return Err(SerializeError::BufferOverflow);
+        }
+
+        buffer[0] = (*self >> 56) as u8;
+        buffer[1] = (*self >> 48) as u8;
+        buffer[2] = (*self >> 40) as u8;
+        buffer[3] = (*self >> 32) as u8;
+        buffer[4] = (*self >> 24) as u8;
+        buffer[5] = (*self >> 16) as u8;
+        buffer[6] = (*self >> 8) as u8;
+        buffer[7] = *self as u8;
+
+        Ok(8)
+    }
+}
+
+// Length prefixed like Vec, so at most 255 bytes of UTF-8
+impl Serialize for String {
+    fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
+        let bytes = self.as_bytes();
+        if bytes.len() > u8::MAX as usize || buffer.len() < 1 + bytes.len() {
+            return Err(SerializeError::BufferOverflow);
+        }
+
+        buffer[0] = bytes.len() as u8;
+        buffer[1..1 + bytes.len()].copy_from_slice(bytes);
+
+        Ok(1 + bytes.len())
+    }
+}
 
 #[derive(Debug)]
 pub enum DeserializeError {
@@ -192,7 +255,7 @@ where
     T: Deserialize,
 {
     fn deserialize(buf: &[u8]) -> Result<(Self, usize), DeserializeError> {
-        if buf.len() < 1 {
+        if buf.is_empty() {
             return Err(DeserializeError::Invalid);
         }
 
@@ -212,7 +275,7 @@ where
     T: Deserialize,
 {
     fn deserialize(buf: &[u8]) -> Result<(Self, usize), DeserializeError> {
-        if buf.len() < 1 {
+        if buf.is_empty() {
             return Err(DeserializeError::Invalid);
         }
 
@@ -254,9 +317,23 @@ impl_deserialize_tuple!(0 A a, 1 B b);
 // TODO add tuples of size 3,4..N when needed
 
 // Primitive implimintations
+impl Deserialize for bool {
+    fn deserialize(buf: &[u8]) -> Result<(Self, usize), DeserializeError> {
+        if buf.is_empty() {
+            return Err(DeserializeError::Invalid);
+        }
+
+        match buf[0] {
+            0 => Ok((false, 1)),
+            1 => Ok((true, 1)),
+            _ => Err(DeserializeError::Invalid),
+        }
+    }
+}
+
 impl Deserialize for u8 {
     fn deserialize(buf: &[u8]) -> Result<(Self, usize), DeserializeError> {
-        if buf.len() < 1 {
+        if buf.is_empty() {
             return Err(DeserializeError::Invalid);
         }
 
@@ -266,7 +343,7 @@ impl Deserialize for u8 {
 
 impl Deserialize for i8 {
     fn deserialize(buf: &[u8]) -> Result<(Self, usize), DeserializeError> {
-        if buf.len() < 1 {
+        if buf.is_empty() {
             return Err(DeserializeError::Invalid);
         }
 
@@ -276,7 +353,7 @@ impl Deserialize for i8 {
 
 impl Deserialize for u16 {
     fn deserialize(buf: &[u8]) -> Result<(Self, usize), DeserializeError> {
-        if buf.len() < 1 {
+        if buf.len() < 2 {
             return Err(DeserializeError::Invalid);
         }
 
@@ -289,7 +366,7 @@ impl Deserialize for u16 {
 
 impl Deserialize for i16 {
     fn deserialize(buf: &[u8]) -> Result<(Self, usize), DeserializeError> {
-        if buf.len() < 1 {
+        if buf.len() < 2 {
             return Err(DeserializeError::Invalid);
         }
 
@@ -330,4 +407,126 @@ impl Deserialize for i32 {
     }
 }
 
-// TODO u64 i64
+impl Deserialize for u64 {
+    fn deserialize(buf: &[u8]) -> Result<(Self, usize), DeserializeError> {
+        if buf.len() < 8 {
+            return Err(DeserializeError::Invalid);
+        }
+
+        let x1 = buf[0] as u64;
+        let x2 = buf[1] as u64;
+        let x3 = buf[2] as u64;
+        let x4 = buf[3] as u64;
+        let x5 = buf[4] as u64;
+        let x6 = buf[5] as u64;
+        let x7 = buf[6] as u64;
+        let x8 = buf[7] as u64;
+
+        Ok((
+            (x1 << 56)
+                | (x2 << 48)
+                | (x3 << 40)
+                | (x4 << 32)
+                | (x5 << 24)
+                | (x6 << 16)
+                | (x7 << 8)
+                | x8,
+            8,
+        ))
+    }
+}
+
+impl Deserialize for i64 {
+    fn deserialize(buf: &[u8]) -> Result<(Self, usize), DeserializeError> {
+        if buf.len() < 8 {
+            return Err(DeserializeError::Invalid);
+        }
+
+        let x1 = buf[0] as i64;
+        let x2 = buf[1] as i64;
+        let x3 = buf[2] as i64;
+        let x4 = buf[3] as i64;
+        let x5 = buf[4] as i64;
+        let x6 = buf[5] as i64;
+        let x7 = buf[6] as i64;
+        let x8 = buf[7] as i64;
+
+        Ok((
+            (x1 << 56)
+                | (x2 << 48)
+                | (x3 << 40)
+                | (x4 << 32)
+                | (x5 << 24)
+                | (x6 << 16)
+                | (x7 << 8)
+                | x8,
+            8,
+        ))
+    }
+}
+
+impl Deserialize for String {
+    fn deserialize(buf: &[u8]) -> Result<(Self, usize), DeserializeError> {
+        if buf.is_empty() {
+            return Err(DeserializeError::Invalid);
+        }
+
+        let len = buf[0] as usize;
+        if buf.len() < 1 + len {
+            return Err(DeserializeError::Invalid);
+        }
+
+        let s =
+            String::from_utf8(buf[1..1 + len].to_vec()).map_err(|_| DeserializeError::Invalid)?;
+
+        Ok((s, 1 + len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: Serialize + Deserialize + PartialEq + fmt::Debug + Copy>(
+        value: T,
+        size: usize,
+    ) {
+        let mut buf = [0; 16];
+        assert_eq!(value.serialize(&mut buf).unwrap(), size);
+        assert_eq!(T::deserialize(&buf[..size]).unwrap(), (value, size));
+        assert!(matches!(
+            value.serialize(&mut buf[..size - 1]),
+            Err(SerializeError::BufferOverflow)
+        ));
+        assert!(matches!(
+            T::deserialize(&buf[..size - 1]),
+            Err(DeserializeError::Invalid)
+        ));
+    }
+
+    #[test]
+    fn integers_round_trip_big_endian() {
+        for value in [0, 1, 0x1234, u16::MAX] {
+            round_trip(value, 2);
+        }
+        for value in [0, -1, 0x1234, i16::MIN, i16::MAX] {
+            round_trip(value, 2);
+        }
+        for value in [0, 1, 0x1234_5678, u32::MAX] {
+            round_trip(value, 4);
+        }
+        for value in [0, -1, 0x1234_5678, i32::MIN, i32::MAX] {
+            round_trip(value, 4);
+        }
+        for value in [0, 1, 0x0123_4567_89ab_cdef, u64::MAX] {
+            round_trip(value, 8);
+        }
+        for value in [0, -1, 0x0123_4567_89ab_cdef, i64::MIN, i64::MAX] {
+            round_trip(value, 8);
+        }
+
+        let mut buf = [0; 8];
+        0x0102_0304_0506_0708u64.serialize(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+}
@@ -62,7 +62,7 @@ pub fn derive_serialize(input: TokenStream) -> TokenStream {
                             .zip(field_names)
                             .map(|(field, field_name)| match &field.ty {
                                 Type::Path(tp) => {
-                                    if let Some(_) = tp.path.get_ident() {
+                                    if tp.path.get_ident().is_some() {
                                         return quote! {
                                             offset += #field_name.serialize(&mut buf[offset..])?;
                                         };
@@ -129,15 +129,9 @@ pub fn derive_deserialize(input: TokenStream) -> TokenStream {
                     let field_name = &f.ident;
                     match &f.ty {
                         Type::Path(field_type) => {
-                            let field_type_quote = quote! { #field_type };
-                            // TODO is this a hack??
-                            let field_type = if field_type_quote.to_string().starts_with("Vec") {
-                                quote! { Vec }
-                            } else {
-                                field_type_quote
-                            };
-                            return quote! {
-                                let (#field_name, size) = #field_type::deserialize(&buf[offset..])?;
+                            // qualified path so generic types like Vec<T> or Option<T> work too
+                            quote! {
+                                let (#field_name, size) = <#field_type>::deserialize(&buf[offset..])?;
                                 offset += size;
                             }
                         }
@@ -201,7 +195,7 @@ pub fn derive_deserialize(input: TokenStream) -> TokenStream {
                             .zip(field_names)
                             .map(|(field, field_name)| match &field.ty {
                                 Type::Path(tp) => {
-                                    if let Some(_) = tp.path.get_ident() {
+                                    if tp.path.get_ident().is_some() {
                                         return quote! {
                                             let (#field_name, size) = #tp::deserialize(&buf[offset..])?;
                                             offset += size;
@@ -1,35 +1,431 @@
 use std::{
-    cmp::min,
-    collections::HashMap,
+    cmp::{max, min, Ordering},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::Write as _,
+    fs,
     io::{self, Read, Write},
-    net::{SocketAddr, TcpListener, TcpStream},
+    net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
     ops::Deref,
+    path::{Path, PathBuf},
+    str::{FromStr, SplitWhitespace},
     sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
         mpsc::{channel, Receiver, RecvTimeoutError, Sender},
-        Arc, RwLock,
+        Arc,
     },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use game_core::{
-    constants,
-    protocol::{self, ClientPacket, Direction, Packet, Player},
-    types::{self, Block, Coords, Map},
+    constants, pathfinding,
+    protocol::{
+        self, ChatChannel, ClientPacket, FlagState, FlagStatus, GameMode, MatchPhase, MatchResults,
+        MatchState, Packet, RejectReason, Score, TeamScores, Winner,
+    },
+    sim::{self, Action, Arena, Event, World, PLAYER_HP, PLAYER_VIEW_RADIUS},
+    types::{self, Block, Direction, ItemKind, Map, Position, Team},
     utils,
+    weapons::{WeaponKind, WeaponSpec},
 };
 use logger::{log, log_error, log_info};
 use proto_dryb::Deserialize;
+use signal_hook::{consts::TERM_SIGNALS, flag};
 
-const PREDICATE_CLIENT_INSIDE_RADIUS: fn(Coords, u8, Coords) -> bool =
+const PREDICATE_CLIENT_INSIDE_RADIUS: fn(Position, u8, Position) -> bool =
     |c1_coords, c1_radius, c2_coords| utils::is_inside_circle(c1_coords, c1_radius, c2_coords);
+const BUF_SIZE_2048: usize = 2048;
+const _BUF_SIZE_1024: usize = 1024;
 const BUF_SIZE_512: usize = 512;
 const _BUF_SIZE_256: usize = 256;
 const _BUF_SIZE_128: usize = 128;
-const _BUF_SIZE_64: usize = 64;
+const BUF_SIZE_64: usize = 64;
 const _BUF_SIZE_32: usize = 32;
-const BUF_SIZE_16: usize = 16;
+const _BUF_SIZE_16: usize = 16;
 const BUF_SIZE_8: usize = 8;
+const DEFAULT_RESPAWN_SECS: u64 = 5;
+const DEFAULT_NAME: &str = "player";
+// Keeps the scoreboard payload inside BUF_SIZE_2048
+const SCOREBOARD_MAX_ROWS: usize = 32;
+const CHAT_PROXIMITY_RADIUS: u8 = 10;
+const DEFAULT_ITEM_SPAWNS: usize = 8;
+const DEFAULT_ITEM_RESPAWN_SECS: u64 = 20;
+// Spawn points get their item kind in this order, wrapping around
+const ITEM_SPAWN_KINDS: [ItemKind; 6] = [
+    ItemKind::HealthPack,
+    ItemKind::Ammo,
+    ItemKind::Armor,
+    ItemKind::Weapon(WeaponKind::Shotgun),
+    ItemKind::Weapon(WeaponKind::Rifle),
+    ItemKind::Weapon(WeaponKind::Grenade),
+];
+const DEFAULT_MIN_PLAYERS: usize = 2;
+const DEFAULT_WARMUP_SECS: u64 = 10;
+const DEFAULT_TIME_LIMIT_SECS: u64 = 300;
+const DEFAULT_SCORE_LIMIT: u16 = 20;
+const DEFAULT_CTF_SCORE_LIMIT: u16 = 3;
+const POST_MATCH_TIME: Duration = Duration::from_secs(10);
+// how long the game loop waits for client events before updating timers and bots
+const TICK: Duration = Duration::from_millis(50);
+const DEFAULT_BOTS: usize = 0;
+const BOT_NAME: &str = "bot";
+const DEFAULT_RECONNECT_GRACE_SECS: u64 = 30;
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 10;
+const PING_INTERVAL: Duration = Duration::from_secs(2);
+const RTT_LOG_INTERVAL: Duration = Duration::from_secs(30);
+const SHUTDOWN_REASON: &str = "Server is shutting down";
+const DEFAULT_MAPS_DIR: &str = "maps";
+// brackets never make it through sanitize_name, so no player can pose as the server
+const SERVER_NAME: &str = "[server]";
+const ADMIN_COMMANDS: &str = "list, kick, ban, unban, say, teleport, sethp, map, shutdown";
+// wrong admin passwords an address may send before it is banned
+const ADMIN_PASSWORD_ATTEMPTS: u8 = 3;
+const DEFAULT_MAX_PLAYERS: usize = 16;
+const DEFAULT_MAX_CONNECTIONS_PER_IP: usize = 4;
+// burst and refill rate per second of the per client rate limits, holding a key down stays
+// below them
+const MOVE_BURST: f32 = 15.0;
+const MOVES_PER_SEC: f32 = 30.0;
+const SHOT_BURST: f32 = 5.0;
+const SHOTS_PER_SEC: f32 = 10.0;
+const CHAT_BURST: f32 = 5.0;
+const CHAT_MESSAGES_PER_SEC: f32 = 0.5;
+const ADMIN_BURST: f32 = 3.0;
+const ADMIN_COMMANDS_PER_SEC: f32 = 1.0;
+// every packet dropped by a rate limit takes one of these, the drops beyond them are violations
+const FLOOD_BURST: f32 = 50.0;
+const FLOOD_DROPS_PER_SEC: f32 = 5.0;
+const DEFAULT_VIOLATION_LIMIT: u16 = 10;
+// actions sent before the client learned about its death are no violation
+const DEAD_ACTION_GRACE: Duration = Duration::from_secs(1);
+
+struct Config {
+    mode: GameMode,
+    respawn_time: Duration,
+    item_spawns: usize,
+    item_respawn_time: Duration,
+    min_players: usize,
+    warmup_time: Duration,
+    time_limit: Duration,
+    // None for the default of the mode
+    score_limit: Option<u16>,
+    // bots fill up the server to this many players
+    bots: usize,
+    bot_difficulty: BotDifficulty,
+    // how long a player who lost the connection stays in the game waiting to be resumed
+    reconnect_grace: Duration,
+    // connections which send nothing for this long are taken as lost
+    idle_timeout: Duration,
+    // told to the clients on shutdown, for servers which are brought back by a supervisor
+    restart_in: Option<u16>,
+    // where the scores are written on shutdown
+    state_file: Option<PathBuf>,
+    // None turns the admin packet off, the console works either way
+    admin_password: Option<String>,
+    // `map <name>` loads <name>.txt from here
+    maps_dir: PathBuf,
+    // players over the network, lost players waiting to be resumed count as well. Bots do not,
+    // they make room for the humans
+    max_players: usize,
+    // joined and pending connections together
+    max_connections_per_ip: usize,
+    // one address per line, read on start and written on every ban change
+    ban_file: Option<PathBuf>,
+    // violations a client gets away with before the punishment
+    violation_limit: u16,
+    punishment: Punishment,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mode: GameMode::default(),
+            respawn_time: Duration::from_secs(DEFAULT_RESPAWN_SECS),
+            item_spawns: DEFAULT_ITEM_SPAWNS,
+            item_respawn_time: Duration::from_secs(DEFAULT_ITEM_RESPAWN_SECS),
+            min_players: DEFAULT_MIN_PLAYERS,
+            warmup_time: Duration::from_secs(DEFAULT_WARMUP_SECS),
+            time_limit: Duration::from_secs(DEFAULT_TIME_LIMIT_SECS),
+            score_limit: None,
+            bots: DEFAULT_BOTS,
+            bot_difficulty: BotDifficulty::Normal,
+            reconnect_grace: Duration::from_secs(DEFAULT_RECONNECT_GRACE_SECS),
+            idle_timeout: Duration::from_secs(DEFAULT_IDLE_TIMEOUT_SECS),
+            restart_in: None,
+            state_file: None,
+            admin_password: None,
+            maps_dir: PathBuf::from(DEFAULT_MAPS_DIR),
+            max_players: DEFAULT_MAX_PLAYERS,
+            max_connections_per_ip: DEFAULT_MAX_CONNECTIONS_PER_IP,
+            ban_file: None,
+            violation_limit: DEFAULT_VIOLATION_LIMIT,
+            punishment: Punishment::Kick,
+        }
+    }
+}
+
+impl Config {
+    fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, ()> {
+        let mut config = Self::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--mode" => {
+                    config.mode = match args.next().as_deref() {
+                        Some("ffa") => GameMode::FreeForAll,
+                        Some("tdm") => GameMode::TeamDeathmatch,
+                        Some("ctf") => GameMode::CaptureTheFlag,
+                        _ => {
+                            log_error!("--mode expects one of: ffa, tdm, ctf");
+                            return Err(());
+                        }
+                    };
+                }
+                "--respawn-secs" => {
+                    config.respawn_time = Duration::from_secs(number_arg(&arg, &mut args)?);
+                }
+                "--item-spawns" => config.item_spawns = number_arg(&arg, &mut args)?,
+                "--item-respawn-secs" => {
+                    config.item_respawn_time = Duration::from_secs(number_arg(&arg, &mut args)?);
+                }
+                "--min-players" => config.min_players = number_arg(&arg, &mut args)?,
+                "--warmup-secs" => {
+                    config.warmup_time = Duration::from_secs(number_arg(&arg, &mut args)?);
+                }
+                "--time-limit-secs" => {
+                    config.time_limit = Duration::from_secs(number_arg(&arg, &mut args)?);
+                }
+                "--score-limit" => config.score_limit = Some(number_arg(&arg, &mut args)?),
+                "--bots" => config.bots = number_arg(&arg, &mut args)?,
+                "--bot-difficulty" => {
+                    config.bot_difficulty = match args.next().as_deref() {
+                        Some("easy") => BotDifficulty::Easy,
+                        Some("normal") => BotDifficulty::Normal,
+                        Some("hard") => BotDifficulty::Hard,
+                        _ => {
+                            log_error!("--bot-difficulty expects one of: easy, normal, hard");
+                            return Err(());
+                        }
+                    };
+                }
+                "--reconnect-grace-secs" => {
+                    config.reconnect_grace = Duration::from_secs(number_arg(&arg, &mut args)?);
+                }
+                "--idle-timeout-secs" => {
+                    config.idle_timeout = Duration::from_secs(number_arg(&arg, &mut args)?);
+                }
+                "--restart-secs" => config.restart_in = Some(number_arg(&arg, &mut args)?),
+                "--state-file" => {
+                    config.state_file = Some(PathBuf::from(string_arg(&arg, &mut args)?));
+                }
+                "--admin-password" => config.admin_password = Some(string_arg(&arg, &mut args)?),
+                "--maps-dir" => config.maps_dir = PathBuf::from(string_arg(&arg, &mut args)?),
+                "--max-players" => config.max_players = number_arg(&arg, &mut args)?,
+                "--max-connections-per-ip" => {
+                    config.max_connections_per_ip = number_arg(&arg, &mut args)?;
+                }
+                "--ban-file" => config.ban_file = Some(PathBuf::from(string_arg(&arg, &mut args)?)),
+                "--violation-limit" => config.violation_limit = number_arg(&arg, &mut args)?,
+                "--punishment" => {
+                    config.punishment = match args.next().as_deref() {
+                        Some("log") => Punishment::Log,
+                        Some("kick") => Punishment::Kick,
+                        Some("ban") => Punishment::Ban,
+                        _ => {
+                            log_error!("--punishment expects one of: log, kick, ban");
+                            return Err(());
+                        }
+                    };
+                }
+                _ => {
+                    log_error!("Unknown argument: {arg}");
+                    return Err(());
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Kills in free for all and team deathmatch, captures in capture the flag
+    fn score_limit(&self) -> u16 {
+        self.score_limit.unwrap_or(match self.mode {
+            GameMode::CaptureTheFlag => DEFAULT_CTF_SCORE_LIMIT,
+            _ => DEFAULT_SCORE_LIMIT,
+        })
+    }
+}
+
+/// Parses the value following `flag`
+fn number_arg<T: FromStr>(flag: &str, args: &mut impl Iterator<Item = String>) -> Result<T, ()> {
+    args.next()
+        .and_then(|value| value.parse().ok())
+        .ok_or(())
+        .map_err(|_| log_error!("{flag} expects a number"))
+}
+
+fn string_arg(flag: &str, args: &mut impl Iterator<Item = String>) -> Result<String, ()> {
+    args.next()
+        .ok_or(())
+        .map_err(|_| log_error!("{flag} expects a value"))
+}
+
+/// What an operator can do to a running server, from the console or over the admin packet
+enum AdminCommand {
+    List,
+    Kick(u32),
+    Ban(IpAddr),
+    Unban(IpAddr),
+    Say(String),
+    Teleport(u32, Position),
+    SetHp(u32, u8),
+    Map(String),
+    Shutdown,
+}
+
+impl FromStr for AdminCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+
+        match command {
+            "list" => Ok(AdminCommand::List),
+            "kick" => Ok(AdminCommand::Kick(admin_arg(&mut words, "kick <id>")?)),
+            "ban" => Ok(AdminCommand::Ban(ip_arg(&mut words, "ban <ip>")?)),
+            "unban" => Ok(AdminCommand::Unban(ip_arg(&mut words, "unban <ip>")?)),
+            "say" => {
+                let text = words.collect::<Vec<_>>().join(" ");
+                match text.is_empty() {
+                    true => Err("Usage: say <message>".to_string()),
+                    false => Ok(AdminCommand::Say(text)),
+                }
+            }
+            "teleport" => {
+                let usage = "teleport <id> <row> <col>";
+                let id = admin_arg(&mut words, usage)?;
+                let row = admin_arg(&mut words, usage)?;
+                let col = admin_arg(&mut words, usage)?;
+                Ok(AdminCommand::Teleport(id, Position::new(row, col)))
+            }
+            "sethp" => {
+                let usage = "sethp <id> <hp>";
+                let id = admin_arg(&mut words, usage)?;
+                Ok(AdminCommand::SetHp(id, admin_arg(&mut words, usage)?))
+            }
+            "map" => Ok(AdminCommand::Map(admin_arg(&mut words, "map <name>")?)),
+            "shutdown" => Ok(AdminCommand::Shutdown),
+            "" => Err(format!("Commands: {ADMIN_COMMANDS}")),
+            _ => Err(format!("Unknown command {command}, try: {ADMIN_COMMANDS}")),
+        }
+    }
+}
+
+/// Parses the next word of an admin command, the usage is the error if that fails
+fn admin_arg<T: FromStr>(words: &mut SplitWhitespace, usage: &str) -> Result<T, String> {
+    words
+        .next()
+        .and_then(|word| word.parse().ok())
+        .ok_or_else(|| format!("Usage: {usage}"))
+}
+
+fn ip_arg(words: &mut SplitWhitespace, usage: &str) -> Result<IpAddr, String> {
+    let addr: String = admin_arg(words, usage)?;
+    // the whole address of a client from `list` works as well
+    addr.parse::<IpAddr>()
+        .or_else(|_| addr.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .map_err(|_| format!("Usage: {usage}"))
+}
+
+/// Things a client playing by the rules never does
+#[derive(Clone, Copy)]
+enum Violation {
+    // sent a packet only the server sends
+    WrongDirection,
+    // kept acting long after it was told it died
+    ActedWhileDead,
+    // kept sending packets faster than the rate limits let through
+    ImpossibleRate,
+    // joined again on a connection which already plays
+    Rejoin,
+}
+
+impl Violation {
+    fn description(self) -> &'static str {
+        match self {
+            Violation::WrongDirection => "sent a server packet",
+            Violation::ActedWhileDead => "acted while dead",
+            Violation::ImpossibleRate => "sent packets at an impossible rate",
+            Violation::Rejoin => "tried to join twice",
+        }
+    }
+}
+
+/// What happens to a client which reaches the violation limit
+#[derive(Clone, Copy)]
+enum Punishment {
+    Log,
+    Kick,
+    Ban,
+}
+
+#[derive(Clone, Copy)]
+enum BotDifficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl BotDifficulty {
+    /// Time between two actions of a bot
+    fn think_interval(self) -> Duration {
+        match self {
+            BotDifficulty::Easy => Duration::from_millis(700),
+            BotDifficulty::Normal => Duration::from_millis(400),
+            BotDifficulty::Hard => Duration::from_millis(200),
+        }
+    }
+
+    /// How far away a bot notices enemies
+    fn sight(self) -> u8 {
+        match self {
+            BotDifficulty::Easy => 3,
+            BotDifficulty::Normal => 5,
+            BotDifficulty::Hard => 7,
+        }
+    }
+}
+
+struct Bot {
+    addr: SocketAddr,
+    next_think_at: Instant,
+    // where the bot heads while no enemy is in sight
+    wander_to: Option<Position>,
+}
+
+enum Connection {
+    Tcp(Arc<TcpStream>),
+    // bots play on the server itself, whatever is sent to them is dropped
+    Bot,
+}
+
+impl Connection {
+    fn write(&self, buf: &[u8]) -> Result<usize, io::Error> {
+        match self {
+            Connection::Tcp(stream) => stream.deref().write(buf),
+            Connection::Bot => Ok(buf.len()),
+        }
+    }
+}
+
+/// Connection which has not joined yet
+struct Pending {
+    stream: Arc<TcpStream>,
+    // when it connected or last sent a packet, silent ones are closed like idle players
+    last_heard: Instant,
+    admin: TokenBucket,
+}
 
 enum ClientEvent {
     Connect {
@@ -41,457 +437,2025 @@ enum ClientEvent {
     },
     Read {
         addr: SocketAddr,
-        bytes: Box<[u8]>,
+        packets: Vec<Packet>,
+    },
+    // the client sent bytes which are no packet, its reading thread has stopped
+    Garbage {
+        addr: SocketAddr,
     },
     Error {
         addr: SocketAddr,
         err: io::Error,
     },
+    // a line typed into the server console
+    Console {
+        line: String,
+    },
 }
 
+/// Server side of a joined player, the game state of it lives in the world
 struct Client {
-    conn: Arc<TcpStream>,
-
     id: u32,
-    coords: Coords,
-    radius: u8,
-    hp: u8,
-    weapon: Weapon,
+    // proves the identity of the player when it comes back on a new connection
+    token: u64,
+    // Some while the connection is lost and the player waits to be resumed
+    disconnected_at: Option<Instant>,
+    respawn_at: Option<Instant>,
+    // told again to a player resuming while it waits for the respawn
+    killed_by: u32,
+    stats: Stats,
+    moves: TokenBucket,
+    shots: TokenBucket,
+    chat: TokenBucket,
+    admin: TokenBucket,
+    // drained by the packets the other buckets drop, jitter bunching a few packets up is
+    // forgiven, a client which empties it commits a violation with every further drop
+    flood: TokenBucket,
+    violations: u16,
+    // sequence number of the last move handled, echoed back in NewCoords
+    last_move: u32,
+    // when the last packet came in from the client
+    last_heard: Instant,
+    // nonce and send time of the ping waiting for its pong
+    ping: Option<(u32, Instant)>,
+    // round trip time measured by the last answered ping
+    rtt: Option<Duration>,
+}
+
+#[derive(Default)]
+struct Stats {
+    kills: u16,
+    deaths: u16,
+    streak: u16,
+    best_streak: u16,
+}
+
+impl Stats {
+    fn record_kill(&mut self) {
+        self.kills = self.kills.saturating_add(1);
+        self.streak = self.streak.saturating_add(1);
+        self.best_streak = max(self.best_streak, self.streak);
+    }
 
-    map_ref: Arc<RwLock<ServerMap>>,
+    fn record_death(&mut self) {
+        self.deaths = self.deaths.saturating_add(1);
+        self.streak = 0;
+    }
 }
 
-struct Weapon {
-    range: u8,
-    _pierce: u8,
-    damage: u8,
+/// Holds up to `capacity` tokens and gets `per_sec` of them back every second, each allowed
+/// packet takes one
+struct TokenBucket {
+    capacity: f32,
+    per_sec: f32,
+    tokens: f32,
+    refilled_at: Instant,
 }
 
-impl Default for Weapon {
-    fn default() -> Self {
+impl TokenBucket {
+    fn new(capacity: f32, per_sec: f32) -> Self {
         Self {
-            range: 5,
-            damage: 1,
-            _pierce: 1,
+            capacity,
+            per_sec,
+            tokens: capacity,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f32() * self.per_sec).min(self.capacity);
+        self.refilled_at = now;
+
+        if self.tokens < 1.0 {
+            return false;
         }
+        self.tokens -= 1.0;
+
+        true
     }
 }
 
 impl Client {
-    fn new_from_conn(conn: Arc<TcpStream>, id: &mut u32, map: &Arc<RwLock<ServerMap>>) -> Self {
-        let (max_x, max_y) = {
-            let map = map.read().unwrap();
-            (map.height, map.width)
-        };
-        let coords = utils::generate_random_coords(max_x, max_y);
-        let new = Self {
-            conn,
-            coords,
-            weapon: Weapon::default(),
-            radius: 5,
-            hp: 10,
-            id: *id,
-            map_ref: Arc::clone(map),
+    fn new(id: u32) -> Self {
+        Self {
+            id,
+            token: utils::generate_session_token(),
+            disconnected_at: None,
+            respawn_at: None,
+            killed_by: id,
+            stats: Stats::default(),
+            moves: TokenBucket::new(MOVE_BURST, MOVES_PER_SEC),
+            shots: TokenBucket::new(SHOT_BURST, SHOTS_PER_SEC),
+            chat: TokenBucket::new(CHAT_BURST, CHAT_MESSAGES_PER_SEC),
+            admin: TokenBucket::new(ADMIN_BURST, ADMIN_COMMANDS_PER_SEC),
+            flood: TokenBucket::new(FLOOD_BURST, FLOOD_DROPS_PER_SEC),
+            violations: 0,
+            last_move: 0,
+            last_heard: Instant::now(),
+            ping: None,
+            rtt: None,
+        }
+    }
+
+    fn score(&self, player: &sim::Player) -> Score {
+        let Stats {
+            kills,
+            deaths,
+            streak,
+            best_streak,
+        } = self.stats;
+
+        Score {
+            id: self.id,
+            name: player.name.clone(),
+            team: player.team,
+            kills,
+            deaths,
+            streak,
+            best_streak,
+        }
+    }
+}
+
+struct Flag {
+    team: Team,
+    home: Position,
+    state: FlagState,
+}
+
+impl Flag {
+    /// Where the flag lies on the map, None while it is carried
+    fn coords(&self) -> Option<Position> {
+        match self.state {
+            FlagState::AtBase => Some(self.home),
+            FlagState::Dropped(coords) => Some(coords),
+            FlagState::Carried(_) => None,
+        }
+    }
+
+    fn status(&self) -> FlagStatus {
+        FlagStatus {
+            team: self.team,
+            state: self.state,
+        }
+    }
+}
+
+struct ItemSpawn {
+    coords: Position,
+    kind: ItemKind,
+    // None while the item is lying on the map
+    respawn_at: Option<Instant>,
+}
+
+/// Single owner of every joined player, only the game thread ever touches it so game logic needs
+/// no locks. Players are kept in id order, which makes everything walking over them behave the
+/// same on every run. The world knows the players by the same ids. A player who lost the
+/// connection stays in here without one until it is resumed or its session expires
+#[derive(Default)]
+struct Entities {
+    clients: BTreeMap<u32, Client>,
+    connections: BTreeMap<u32, Connection>,
+    ids: HashMap<SocketAddr, u32>,
+    sessions: HashMap<u64, u32>,
+}
+
+impl Entities {
+    fn insert(&mut self, addr: SocketAddr, conn: Connection, client: Client) {
+        self.ids.insert(addr, client.id);
+        self.sessions.insert(client.token, client.id);
+        self.connections.insert(client.id, conn);
+        self.clients.insert(client.id, client);
+    }
+
+    fn remove(&mut self, id: u32) -> Option<Client> {
+        self.ids.retain(|_, other| *other != id);
+        self.connections.remove(&id);
+        let client = self.clients.remove(&id)?;
+        self.sessions.remove(&client.token);
+
+        Some(client)
+    }
+
+    /// Forgets the connection but keeps the player, returns its id
+    fn disconnect(&mut self, addr: SocketAddr) -> Option<u32> {
+        let id = self.ids.remove(&addr)?;
+        self.connections.remove(&id);
+
+        Some(id)
+    }
+
+    /// Closes the connection of the player, which keeps it like a lost one
+    fn close(&mut self, id: u32) {
+        self.ids.retain(|_, other| *other != id);
+        if let Some(Connection::Tcp(stream)) = self.connections.remove(&id) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Hands the player over to a new connection, closing the old one if the server has not
+    /// noticed yet that it is gone
+    fn reconnect(&mut self, id: u32, addr: SocketAddr, conn: Connection) {
+        self.close(id);
+        self.ids.insert(addr, id);
+        self.connections.insert(id, conn);
+    }
+
+    /// Closes every connection once what has been written to it is sent
+    fn close_all(&mut self) {
+        self.ids.clear();
+        for (_, conn) in std::mem::take(&mut self.connections) {
+            if let Connection::Tcp(stream) = conn {
+                let _ = stream.deref().flush();
+                let _ = stream.shutdown(Shutdown::Write);
+            }
+        }
+    }
+
+    /// Players connected over the network, bots and lost connections left out
+    fn remote_ids(&self) -> Vec<u32> {
+        self.connections
+            .iter()
+            .filter(|(_, conn)| matches!(conn, Connection::Tcp(_)))
+            .map(|(&id, _)| id)
+            .collect()
+    }
+
+    fn session(&self, token: u64) -> Option<u32> {
+        self.sessions.get(&token).copied()
+    }
+
+    fn id(&self, addr: SocketAddr) -> Option<u32> {
+        self.ids.get(&addr).copied()
+    }
+
+    /// None while the connection is lost
+    fn addr(&self, id: u32) -> Option<SocketAddr> {
+        self.ids
+            .iter()
+            .find(|(_, &other)| other == id)
+            .map(|(&addr, _)| addr)
+    }
+
+    fn is_remote(&self, id: u32) -> bool {
+        matches!(self.connections.get(&id), Some(Connection::Tcp(_)))
+    }
+
+    /// Players connected from the address
+    fn ids_from(&self, ip: IpAddr) -> Vec<u32> {
+        self.ids
+            .iter()
+            .filter(|(addr, _)| addr.ip() == ip)
+            .map(|(_, &id)| id)
+            .collect()
+    }
+
+    fn get(&self, id: u32) -> Option<&Client> {
+        self.clients.get(&id)
+    }
+
+    fn get_mut(&mut self, id: u32) -> Option<&mut Client> {
+        self.clients.get_mut(&id)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Client> {
+        self.clients.values()
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut Client> {
+        self.clients.values_mut()
+    }
+
+    fn ids(&self) -> Vec<u32> {
+        self.clients.keys().copied().collect()
+    }
+
+    fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    fn send(&self, id: u32, buf: &[u8]) -> Result<usize, io::Error> {
+        match self.connections.get(&id) {
+            Some(conn) => conn.write(buf),
+            None => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    fn broadcast(&self, buf: &[u8]) {
+        for conn in self.connections.values() {
+            let _ = conn.write(buf);
+        }
+    }
+}
+
+struct Server {
+    // connected but not joined yet, waiting for ClientPacket::Join
+    pending: HashMap<SocketAddr, Pending>,
+    entities: Entities,
+    id_counter: u32,
+    world: World,
+    item_spawns: Vec<ItemSpawn>,
+    team_scores: TeamScores,
+    // only used in capture the flag
+    flags: Vec<Flag>,
+    // bots are in the entities as well, under made up addresses
+    bots: Vec<Bot>,
+    bot_counter: u16,
+    phase: MatchPhase,
+    // None for phases which only end on a player count change
+    phase_ends_at: Option<Instant>,
+    ping_counter: u32,
+    next_ping_at: Instant,
+    next_rtt_log_at: Instant,
+    // nobody connects from these
+    bans: HashSet<IpAddr>,
+    // wrong admin passwords per address since its last right one
+    admin_failures: HashMap<IpAddr, u8>,
+    // set by the shutdown admin command, the game loop acts on it
+    shutdown_requested: bool,
+    config: Config,
+}
+
+impl Server {
+    fn new(config: Config) -> Self {
+        let (arena, item_spawns, flags) = new_arena(&config, &utils::generate_map());
+        let bans = config
+            .ban_file
+            .as_deref()
+            .map(load_bans)
+            .unwrap_or_default();
+
+        Self {
+            world: World::new(arena, Instant::now()),
+            id_counter: 0,
+            pending: HashMap::new(),
+            entities: Entities::default(),
+            item_spawns,
+            team_scores: TeamScores::default(),
+            flags,
+            bots: vec![],
+            bot_counter: 0,
+            phase: MatchPhase::Lobby,
+            phase_ends_at: None,
+            ping_counter: 0,
+            next_ping_at: Instant::now(),
+            next_rtt_log_at: Instant::now() + RTT_LOG_INTERVAL,
+            bans,
+            admin_failures: HashMap::new(),
+            shutdown_requested: false,
+            config,
+        }
+    }
+
+    /// New players even out the teams, red gets them on a tie
+    fn smaller_team(&self) -> Team {
+        let members = |team| {
+            self.world
+                .players()
+                .filter(|p| p.team == Some(team))
+                .count()
         };
-        *id += 1;
-        new
-    }
-
-    fn do_shoot(&self, direction: Direction, buf: &mut [u8]) {
-        let &Client {
-            coords: (x, y),
-            weapon: Weapon { range, damage, .. },
-            ..
-        } = self;
-        let (h, w) = {
-            let map = self.map_ref.read().unwrap();
-            (map.height as u16, map.width as u16)
+
+        if members(Team::Blue) < members(Team::Red) {
+            Team::Blue
+        } else {
+            Team::Red
+        }
+    }
+
+    /// Sanitized version of the requested name, suffixed with a number if it is already taken
+    fn unique_name(&self, requested: &str) -> String {
+        let mut base = sanitize_name(requested);
+        if base.is_empty() {
+            base = DEFAULT_NAME.to_string();
+        }
+
+        let is_taken = |name: &str| {
+            self.world
+                .players()
+                .any(|p| p.name.eq_ignore_ascii_case(name))
         };
-        let point_from = match direction {
-            Direction::Up => (x.checked_sub(1).unwrap_or(0), y),
-            Direction::Down => (min(x + 1, h), y),
-            Direction::Left => (x, y.checked_sub(1).unwrap_or(0)),
-            Direction::Right => (x, min(y + 1, w)),
+        if !is_taken(&base) {
+            return base;
+        }
+
+        (2..)
+            .map(|n: u32| {
+                let suffix = n.to_string();
+                let mut name = base.clone();
+                name.truncate(constants::MAX_NAME_LEN - suffix.len());
+                name + &suffix
+            })
+            .find(|name| !is_taken(name))
+            .unwrap()
+    }
+
+    fn send_inventory(&self, id: u32, buf: &mut [u8]) -> Result<(), String> {
+        let player = self.world.player(id).ok_or("Player is gone")?;
+        let n = protocol::generate_inventory_payload(buf, player.inventory())
+            .map_err(|_| "Error during generating payload inventory")?;
+        let _ = self.entities.send(id, &buf[..n]);
+
+        Ok(())
+    }
+
+    fn send_weapon_state(&self, id: u32, buf: &mut [u8]) -> Result<(), String> {
+        let player = self.world.player(id).ok_or("Player is gone")?;
+        let n = protocol::generate_weapon_state_payload(buf, player.current_weapon().state())
+            .map_err(|_| "Error during generating payload weapon state")?;
+        let _ = self.entities.send(id, &buf[..n]);
+
+        Ok(())
+    }
+
+    /// Sends the new look of a cell to everyone who sees it
+    fn notify_cell_changed(&self, cell: types::MapCell, buf: &mut [u8]) -> Result<(), String> {
+        let coords = cell.coords;
+        let n = protocol::generate_map_cell_changed_payload(buf, cell)
+            .map_err(|_| "Error during generating payload map cell changed")?;
+        for p in self.world.players_within(coords, PLAYER_VIEW_RADIUS) {
+            if !p.dead && PREDICATE_CLIENT_INSIDE_RADIUS(p.coords, p.radius, coords) {
+                let _ = self.entities.send(p.id, &buf[..n]);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends the packet once to everyone alive who sees at least one of the cells
+    fn send_to_observers(&self, cells: &[Position], packet: &[u8]) {
+        let mut observers = BTreeSet::new();
+        for &cell in cells {
+            for p in self.world.players_within(cell, PLAYER_VIEW_RADIUS) {
+                if !p.dead && PREDICATE_CLIENT_INSIDE_RADIUS(p.coords, p.radius, cell) {
+                    observers.insert(p.id);
+                }
+            }
+        }
+
+        for id in observers {
+            let _ = self.entities.send(id, packet);
+        }
+    }
+
+    fn client_connected(&mut self, addr: SocketAddr, stream: Arc<TcpStream>) {
+        if self.bans.contains(&addr.ip()) {
+            log_info!("Refused banned client {addr}");
+            let _ = stream.shutdown(Shutdown::Both);
+            return;
+        }
+        let connections = self.pending.keys().filter(|a| a.ip() == addr.ip()).count()
+            + self.entities.ids_from(addr.ip()).len();
+        if connections >= self.config.max_connections_per_ip {
+            log_info!("Refused client {addr}, too many connections from the address");
+            let _ = stream.shutdown(Shutdown::Both);
+            return;
+        }
+        log_info!("Client {addr} connected");
+
+        self.pending.insert(
+            addr,
+            Pending {
+                stream,
+                last_heard: Instant::now(),
+                admin: TokenBucket::new(ADMIN_BURST, ADMIN_COMMANDS_PER_SEC),
+            },
+        );
+    }
+
+    fn client_joined(
+        &mut self,
+        buf: &mut [u8],
+        addr: SocketAddr,
+        conn: Connection,
+        requested_name: &str,
+    ) -> Result<(), ()> {
+        if let Connection::Tcp(stream) = &conn {
+            if self.humans() >= self.config.max_players {
+                log_info!("Refused client {addr}, the server is full");
+                let n = protocol::generate_server_full_payload(buf)
+                    .map_err(|_| log_error!("Could not generate server_full"))?;
+                let _ = stream.deref().write(&buf[..n]);
+                let _ = stream.shutdown(Shutdown::Both);
+                return Ok(());
+            }
+        }
+
+        let name = self.unique_name(requested_name);
+        log_info!("Client {addr} joined as {name}");
+
+        let team = self.config.mode.has_teams().then(|| self.smaller_team());
+        let Some(coords) = self.world.arena.random_free_coords(team) else {
+            log_error!("No free cell left for client {addr}");
+            if let Connection::Tcp(stream) = conn {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+            return Ok(());
         };
-        let range = range as u16;
-        let point_to = match direction {
-            Direction::Up => (x.checked_sub(1 + range).unwrap_or(0), y),
-            Direction::Down => (min(x + 1 + range, h), y),
-            Direction::Left => (x, y.checked_sub(1 + range).unwrap_or(0)),
-            Direction::Right => (x, min(y + 1 + range, w)),
+        let id = self.id_counter;
+        self.id_counter += 1;
+
+        self.world.add_player(id, name, team, coords);
+        self.entities.insert(addr, conn, Client::new(id));
+        self.send_welcome(id, buf)?;
+
+        let player = self.world.player(id).ok_or(())?;
+        let n = protocol::generate_move_notify_payload(buf, coords, id, player.name.clone(), team)
+            .map_err(|_| ())?;
+        let players_seeing_client = self
+            .world
+            .players_within(coords, PLAYER_VIEW_RADIUS)
+            .filter(|p| p.id != id && utils::is_inside_circle(p.coords, p.radius, coords));
+        for other in players_seeing_client {
+            log_info!("Sending move notification to player with id: {}", other.id);
+            // players waiting to be resumed have no connection to write to
+            let _ = self.entities.send(other.id, &buf[..n]).map_err(|err| {
+                log_error!("Could not notify player {} about the move: {err}", other.id)
+            });
+        }
+
+        // also tells the new player which phase the match is in
+        self.broadcast_match_state(buf)
+    }
+
+    /// Gives the player back to a client which lost its connection, a session which has already
+    /// expired joins anew
+    fn client_resumed(
+        &mut self,
+        buf: &mut [u8],
+        addr: SocketAddr,
+        stream: Arc<TcpStream>,
+        token: u64,
+        requested_name: &str,
+    ) -> Result<(), ()> {
+        let Some(id) = self.entities.session(token) else {
+            log_info!("Client {addr} has no session to resume");
+            return self.client_joined(buf, addr, Connection::Tcp(stream), requested_name);
         };
+        log_info!("Client {addr} resumed player: {id}");
+
+        self.entities.reconnect(id, addr, Connection::Tcp(stream));
+        let client = self.entities.get_mut(id).ok_or(())?;
+        client.disconnected_at = None;
+        client.last_heard = Instant::now();
+        self.send_welcome(id, buf)?;
+
+        let n = protocol::generate_match_state_payload(buf, self.match_state())
+            .map_err(|_| log_error!("Could not generate match_state"))?;
+        let _ = self.entities.send(id, &buf[..n]);
+
+        Ok(())
+    }
+
+    /// Everything a client needs to know to play as the player, sent on joining and resuming
+    fn send_welcome(&self, id: u32, buf: &mut [u8]) -> Result<(), ()> {
+        let player = self.world.player(id).ok_or(())?;
+        let client = self.entities.get(id).ok_or(())?;
+        let (coords, radius) = (player.coords, player.radius);
+        let players_inside_radius = self.world.visible_players(coords, radius, id);
+        let visible_coords = self.world.visible_cells(coords, radius);
+        let n = protocol::generate_initial_payload(
+            buf,
+            id,
+            player.name.clone(),
+            coords,
+            radius,
+            (player.hp, PLAYER_HP),
+            player.current_weapon().state(),
+            visible_coords,
+            players_inside_radius,
+            client.token,
+        )
+        .map_err(|_| log_error!("Could not generate payload"))?;
+
+        self.entities
+            .send(id, &buf[..n])
+            .map_err(|err| log_error!("Could not write to client: {id}, {err}"))?;
+        self.send_inventory(id, buf)
+            .map_err(|err| log_error!("{err}"))?;
+        let n = protocol::generate_game_info_payload(buf, self.config.mode, player.team)
+            .map_err(|_| log_error!("Could not generate game_info"))?;
+        let _ = self.entities.send(id, &buf[..n]);
+        if self.config.mode.has_teams() {
+            let n = protocol::generate_team_scores_payload(buf, self.team_scores)
+                .map_err(|_| log_error!("Could not generate team_scores"))?;
+            let _ = self.entities.send(id, &buf[..n]);
+        }
+        for flag in &self.flags {
+            let n = protocol::generate_flag_status_payload(buf, flag.status())
+                .map_err(|_| log_error!("Could not generate flag_status"))?;
+            let _ = self.entities.send(id, &buf[..n]);
+        }
+
+        if let Some(at) = client.respawn_at {
+            let respawn_in = at
+                .saturating_duration_since(Instant::now())
+                .as_secs_f32()
+                .ceil() as u64;
+            let n = protocol::generate_player_died_payload(
+                buf,
+                client.killed_by,
+                min(respawn_in, u8::MAX as u64) as u8,
+            )
+            .map_err(|_| log_error!("Could not generate player_died"))?;
+            let _ = self.entities.send(id, &buf[..n]);
+        }
+
+        Ok(())
+    }
+
+    /// The player stays in the game for the reconnect grace, waiting to be resumed
+    fn client_disconnected(&mut self, addr: SocketAddr) {
+        log_info!("Client {addr} disconnected");
+
+        if self.pending.remove(&addr).is_some() {
+            return;
+        }
+
+        // the player may have been resumed on a new connection already
+        let Some(id) = self.entities.disconnect(addr) else {
+            return;
+        };
+        if let Some(client) = self.entities.get_mut(id) {
+            client.disconnected_at = Some(Instant::now());
+        }
+    }
+
+    /// Pings the connected players, closes the connections which have gone silent, joined or not,
+    /// and logs the round trip times now and then
+    fn heartbeat(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+        let now = Instant::now();
+
+        // a half open connection never tells the server it is gone, so silence has to
+        for id in self.entities.remote_ids() {
+            let client = self.entities.get_mut(id).ok_or(())?;
+            if now.duration_since(client.last_heard) < self.config.idle_timeout {
+                continue;
+            }
+            log_info!("Player: {id} timed out");
+            client.disconnected_at = Some(now);
+            client.ping = None;
+            self.entities.close(id);
+        }
+        self.pending.retain(|addr, pending| {
+            let idle = now.duration_since(pending.last_heard) >= self.config.idle_timeout;
+            if idle {
+                log_info!("Client {addr} timed out before joining");
+                let _ = pending.stream.shutdown(Shutdown::Both);
+            }
+            !idle
+        });
+
+        if now >= self.next_ping_at {
+            self.next_ping_at = now + PING_INTERVAL;
+            for id in self.entities.remote_ids() {
+                self.ping_counter = self.ping_counter.wrapping_add(1);
+                let n = protocol::generate_ping_payload(buf, self.ping_counter)
+                    .map_err(|_| log_error!("Could not generate ping"))?;
+                let _ = self.entities.send(id, &buf[..n]);
+                self.entities.get_mut(id).ok_or(())?.ping = Some((self.ping_counter, now));
+            }
+        }
+
+        if now >= self.next_rtt_log_at {
+            self.next_rtt_log_at = now + RTT_LOG_INTERVAL;
+            for id in self.entities.remote_ids() {
+                let client = self.entities.get(id).ok_or(())?;
+                match client.rtt {
+                    Some(rtt) => log_info!("Player: {id} rtt {}ms", rtt.as_millis()),
+                    None => log_info!("Player: {id} rtt unknown"),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The client sent something which is not a packet, its connection is closed so the game goes
+    /// on for everybody else. The player can still resume
+    fn client_failed(&mut self, addr: SocketAddr) {
+        log_error!("Closing the connection of client {addr}");
+
+        if let Some(pending) = self.pending.remove(&addr) {
+            let _ = pending.stream.shutdown(Shutdown::Both);
+            return;
+        }
+
+        let Some(id) = self.entities.id(addr) else {
+            return;
+        };
+        self.entities.close(id);
+        if let Some(client) = self.entities.get_mut(id) {
+            client.disconnected_at = Some(Instant::now());
+        }
+    }
+
+    /// Tells every client why the server goes away and when it is back, then closes the
+    /// connections and saves the scores if configured to
+    fn shutdown(&mut self, reason: &str, buf: &mut [u8]) -> Result<(), ()> {
+        log_info!("Shutting down: {reason}");
+
+        let n = protocol::generate_server_shutdown_payload(
+            buf,
+            reason.to_string(),
+            self.config.restart_in,
+        )
+        .map_err(|_| log_error!("Could not generate server_shutdown"))?;
+        self.entities.broadcast(&buf[..n]);
+        self.entities.close_all();
+        for (_, pending) in self.pending.drain() {
+            let _ = pending.stream.shutdown(Shutdown::Both);
+        }
+
+        match &self.config.state_file {
+            Some(path) => self.save_state(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the state of the match as text, one player per line
+    fn save_state(&self, path: &Path) -> Result<(), ()> {
+        let mut state = format!("mode {:?}\nphase {:?}\n", self.config.mode, self.phase);
+        if self.config.mode.has_teams() {
+            let TeamScores { red, blue } = self.team_scores;
+            let _ = writeln!(state, "teams red {red} blue {blue}");
+        }
+        for score in self.scoreboard() {
+            let _ = writeln!(
+                state,
+                "player {} kills {} deaths {} best_streak {}",
+                score.name, score.kills, score.deaths, score.best_streak
+            );
+        }
+
+        fs::write(path, state)
+            .map_err(|err| log_error!("Could not save the state to {}: {err}", path.display()))?;
+        log_info!("Saved the state to {}", path.display());
+
+        Ok(())
+    }
+
+    /// Runs a command which came over the network, if the password is right. An address which
+    /// keeps guessing it gets banned
+    fn remote_admin(
+        &mut self,
+        addr: SocketAddr,
+        password: &str,
+        command: &str,
+        buf: &mut [u8],
+    ) -> String {
+        match &self.config.admin_password {
+            None => "Remote admin is disabled".to_string(),
+            Some(expected) if !passwords_match(expected, password) => {
+                let failures = self.admin_failures.entry(addr.ip()).or_default();
+                *failures += 1;
+                log_error!("Client {addr} sent a wrong admin password, {failures} in a row");
+                if *failures < ADMIN_PASSWORD_ATTEMPTS {
+                    return "Wrong password".to_string();
+                }
+
+                self.admin_failures.remove(&addr.ip());
+                log_info!("Banning {}, too many wrong admin passwords", addr.ip());
+                if let Err(err) = self.ban(addr.ip(), buf) {
+                    log_error!("{err}");
+                }
+                "Wrong password, banned".to_string()
+            }
+            Some(_) => {
+                self.admin_failures.remove(&addr.ip());
+                log_info!("Client {addr} runs admin command: {command}");
+                self.admin(command, buf)
+            }
+        }
+    }
+
+    /// Runs an admin command, returns the answer to it which may span several lines
+    fn admin(&mut self, line: &str, buf: &mut [u8]) -> String {
+        let command = match line.parse::<AdminCommand>() {
+            Ok(command) => command,
+            Err(usage) => return usage,
+        };
+
+        let result = match command {
+            AdminCommand::List => Ok(self.player_list()),
+            AdminCommand::Kick(id) => self
+                .kick(id, "Kicked by an admin", buf)
+                .map(|_| format!("Kicked player {id}")),
+            AdminCommand::Ban(ip) => self.ban(ip, buf),
+            AdminCommand::Unban(ip) => self.unban(ip),
+            AdminCommand::Say(text) => self.say(&text, buf),
+            AdminCommand::Teleport(id, to) => self.teleport(id, to, buf),
+            AdminCommand::SetHp(id, hp) => self.set_hp(id, hp, buf),
+            AdminCommand::Map(name) => self.change_map(&name, buf),
+            AdminCommand::Shutdown => {
+                self.shutdown_requested = true;
+                Ok("Shutting down".to_string())
+            }
+        };
+
+        result.unwrap_or_else(|err| err)
+    }
+
+    /// One line per player: id, name, team, hp, kills, deaths, violations, round trip time and
+    /// where it plays from
+    fn player_list(&self) -> String {
+        let lines = self
+            .entities
+            .iter()
+            .filter_map(|c| {
+                let player = self.world.player(c.id)?;
+                let rtt = c
+                    .rtt
+                    .map_or("-".to_string(), |rtt| format!("{}ms", rtt.as_millis()));
+                let from = match self.entities.addr(c.id) {
+                    Some(addr) if addr.ip().is_unspecified() => "bot".to_string(),
+                    Some(addr) => addr.to_string(),
+                    None => "lost".to_string(),
+                };
+
+                Some(format!(
+                    "{} {} {} hp {} k {} d {} v {} rtt {rtt} {from}",
+                    c.id,
+                    player.name,
+                    player.team.map_or("-", |team| team.name()),
+                    player.hp,
+                    c.stats.kills,
+                    c.stats.deaths,
+                    c.violations,
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        match lines.is_empty() {
+            true => "No players".to_string(),
+            false => lines.join("\n"),
+        }
+    }
+
+    /// Removes the player right away, telling it why. It can not resume
+    fn kick(&mut self, id: u32, reason: &str, buf: &mut [u8]) -> Result<(), String> {
+        if self.entities.get(id).is_none() {
+            return Err(format!("No player with id {id}"));
+        }
+        log_info!("Kicking player: {id}, {reason}");
+
+        if let Ok(n) = protocol::generate_kicked_payload(buf, reason.to_string()) {
+            let _ = self.entities.send(id, &buf[..n]);
+        }
+        self.bots
+            .retain(|bot| self.entities.id(bot.addr) != Some(id));
+        self.entities.close(id);
+
+        self.remove_client(id, buf)
+            .map_err(|_| format!("Could not remove player {id}"))
+    }
+
+    /// Kicks everyone connected from the address and refuses it from now on
+    fn ban(&mut self, ip: IpAddr, buf: &mut [u8]) -> Result<String, String> {
+        self.bans.insert(ip);
+        self.save_bans()?;
+
+        let kicked = self.entities.ids_from(ip);
+        for &id in &kicked {
+            self.kick(id, "Banned", buf)?;
+        }
+        self.pending.retain(|addr, pending| {
+            let banned = addr.ip() == ip;
+            if banned {
+                let _ = pending.stream.shutdown(Shutdown::Both);
+            }
+            !banned
+        });
+
+        Ok(format!("Banned {ip}, kicked {} players", kicked.len()))
+    }
+
+    fn unban(&mut self, ip: IpAddr) -> Result<String, String> {
+        if !self.bans.remove(&ip) {
+            return Err(format!("{ip} is not banned"));
+        }
+        self.save_bans()?;
+
+        Ok(format!("Unbanned {ip}"))
+    }
+
+    /// Writes the bans to the ban file sorted, one address per line
+    fn save_bans(&self) -> Result<(), String> {
+        let Some(path) = &self.config.ban_file else {
+            return Ok(());
+        };
+        let bans = self.bans.iter().collect::<BTreeSet<_>>();
+        let contents = bans.iter().fold(String::new(), |mut contents, ip| {
+            let _ = writeln!(contents, "{ip}");
+            contents
+        });
+
+        fs::write(path, contents)
+            .map_err(|err| format!("Could not save the bans to {}: {err}", path.display()))
+    }
+
+    fn say(&self, text: &str, buf: &mut [u8]) -> Result<String, String> {
+        let text = sanitize_chat(text).ok_or("Nothing to say")?;
+        let n = protocol::generate_chat_message_payload(
+            buf,
+            (constants::SERVER_ID, SERVER_NAME.to_string()),
+            ChatChannel::Global,
+            text,
+        )
+        .map_err(|_| "Could not generate chat_message")?;
+        self.entities.broadcast(&buf[..n]);
+
+        Ok("Sent".to_string())
+    }
+
+    fn teleport(&mut self, id: u32, to: Position, buf: &mut [u8]) -> Result<String, String> {
+        let moved = self
+            .world
+            .teleport(id, to)
+            .map_err(|reason| format!("Could not teleport player {id}: {}", reason.message()))?;
+        self.handle_events(vec![moved], buf);
+
+        Ok(format!("Teleported player {id} to {}:{}", to.row, to.col))
+    }
+
+    fn set_hp(&mut self, id: u32, hp: u8, buf: &mut [u8]) -> Result<String, String> {
+        let hp = self
+            .world
+            .set_hp(id, hp)
+            .map_err(|reason| format!("Could not set hp of player {id}: {}", reason.message()))?;
+        let n = protocol::generate_hp_changed_payload(buf, hp)
+            .map_err(|_| "Could not generate hp_changed")?;
+        let _ = self.entities.send(id, &buf[..n]);
+
+        Ok(format!("Player {id} has {hp} hp"))
+    }
+
+    /// Starts over on the named map from the maps dir, `random` generates one
+    fn change_map(&mut self, name: &str, buf: &mut [u8]) -> Result<String, String> {
+        let map = match name {
+            "random" => utils::generate_map(),
+            _ => load_map(&self.config.maps_dir, name)?,
+        };
+
+        self.reset_match(Some(map), buf)
+            .map_err(|_| "Could not reset the match".to_string())?;
+        self.set_phase(MatchPhase::Lobby, None, buf)
+            .map_err(|_| "Could not go back to the lobby".to_string())?;
+
+        Ok(format!("Changed the map to {name}"))
+    }
+
+    /// Removes the players whose connection has been lost for longer than the reconnect grace
+    fn expire_sessions(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+        let now = Instant::now();
+        let expired = self
+            .entities
+            .iter()
+            .filter(|c| {
+                c.disconnected_at
+                    .is_some_and(|at| now.duration_since(at) >= self.config.reconnect_grace)
+            })
+            .map(|c| c.id)
+            .collect::<Vec<_>>();
+
+        for id in expired {
+            log_info!("Session of player: {id} expired");
+            self.remove_client(id, buf)?;
+        }
+
+        Ok(())
+    }
+
+    fn remove_client(&mut self, id: u32, buf: &mut [u8]) -> Result<(), ()> {
+        self.entities
+            .remove(id)
+            .ok_or(())
+            .map_err(|_| log_error!("Did not find client {id} on removal"))?;
+        let coords = self.world.remove_player(id).ok_or(())?.coords;
+
+        let n = protocol::generate_player_disconnected(buf, id)
+            .map_err(|_| log_error!("Could not generate player_disconnected"))?;
+        self.entities.broadcast(&buf[..n]);
+
+        self.drop_flags(id, coords, buf)?;
+
+        self.broadcast_match_state(buf)
+    }
+
+    /// Handles every packet of the read, the rate limits have to see each of them
+    fn client_sent(&mut self, addr: SocketAddr, packets: Vec<Packet>, buf: &mut [u8]) {
+        for packet in packets {
+            // the server failing at a packet is no reason to drop the client
+            if self.client_packet(addr, packet, buf).is_err() {
+                log_error!("Could not handle a packet of client {addr}");
+            }
+
+            // the rest of a kicked client is dropped
+            if self.entities.id(addr).is_none() && !self.pending.contains_key(&addr) {
+                break;
+            }
+        }
+    }
+
+    fn client_packet(
+        &mut self,
+        addr: SocketAddr,
+        packet: Packet,
+        buf: &mut [u8],
+    ) -> Result<(), ()> {
+        if let Some(mut pending) = self.pending.remove(&addr) {
+            pending.last_heard = Instant::now();
+            let stream = pending.stream.clone();
+            match packet {
+                Packet::Client(ClientPacket::Join(name)) => {
+                    self.client_joined(buf, addr, Connection::Tcp(stream), &name)?
+                }
+                Packet::Client(ClientPacket::Resume(token, name)) => {
+                    self.client_resumed(buf, addr, stream, token, &name)?
+                }
+                // admin tools do not have to join
+                Packet::Client(ClientPacket::Admin(password, command)) => {
+                    if !pending.admin.take(Instant::now()) {
+                        log_error!("Client {addr} is sending admin commands too fast");
+                        self.pending.insert(addr, pending);
+                        return Ok(());
+                    }
+                    let reply = self.remote_admin(addr, &password, &command, buf);
+                    send_admin_reply(&reply, buf, |bytes| {
+                        let _ = stream.deref().write(bytes);
+                    });
+                    if self.bans.contains(&addr.ip()) {
+                        let _ = stream.shutdown(Shutdown::Both);
+                    } else {
+                        self.pending.insert(addr, pending);
+                    }
+                }
+                _ => {
+                    log_error!("Client {addr} sent a packet before joining");
+                    self.pending.insert(addr, pending);
+                }
+            }
+            return Ok(());
+        }
+
+        // packets still arriving on a connection which a resume has replaced
+        let Some(id) = self.entities.id(addr) else {
+            log_error!("Client {addr} is not connected to any player");
+            return Ok(());
+        };
+
+        self.entities.get_mut(id).ok_or(())?.last_heard = Instant::now();
+
+        let action = match packet {
+            Packet::Client(cp) => match cp {
+                ClientPacket::Move(seq, direction) => {
+                    log_info!("Got Move client packet with direction: {:?}", direction);
+                    self.entities.get_mut(id).ok_or(())?.last_move = seq;
+                    Action::Move(id, direction)
+                }
+                ClientPacket::Shoot(direction) => Action::Shoot(id, direction),
+                ClientPacket::SwitchWeapon(kind) => Action::SwitchWeapon(id, kind),
+                ClientPacket::Reload => Action::Reload(id),
+                ClientPacket::UseHealthPack => Action::UseHealthPack(id),
+                ClientPacket::Scoreboard => {
+                    let n = protocol::generate_scoreboard_payload(buf, self.scoreboard())
+                        .map_err(|_| log_error!("Could not generate scoreboard"))?;
+                    let _ = self.entities.send(id, &buf[..n]);
+                    return Ok(());
+                }
+                ClientPacket::Join(_) | ClientPacket::Resume(..) => {
+                    return self.violation(id, Violation::Rejoin, buf);
+                }
+                ClientPacket::Chat(channel, text) => return self.chat(id, channel, &text, buf),
+                ClientPacket::Ping(nonce, timestamp) => {
+                    let n = protocol::generate_pong_payload(buf, nonce, timestamp)
+                        .map_err(|_| log_error!("Could not generate pong"))?;
+                    let _ = self.entities.send(id, &buf[..n]);
+                    return Ok(());
+                }
+                ClientPacket::Admin(password, command) => {
+                    if !self.take_token(id, |client| &mut client.admin, buf)? {
+                        log_error!("Client {id} is sending admin commands too fast");
+                        return Ok(());
+                    }
+                    let reply = self.remote_admin(addr, &password, &command, buf);
+                    send_admin_reply(&reply, buf, |bytes| {
+                        let _ = self.entities.send(id, bytes);
+                    });
+                    return Ok(());
+                }
+                ClientPacket::Pong(nonce) => {
+                    let client = self.entities.get_mut(id).ok_or(())?;
+                    if let Some((sent_nonce, sent_at)) = client.ping {
+                        if sent_nonce == nonce {
+                            client.rtt = Some(sent_at.elapsed());
+                            client.ping = None;
+                        }
+                    }
+                    return Ok(());
+                }
+            },
+            Packet::Server(_) => return self.violation(id, Violation::WrongDirection, buf),
+        };
+
+        if self.acted_while_dead(id) {
+            self.violation(id, Violation::ActedWhileDead, buf)?;
+            if self.entities.get(id).is_none() {
+                return Ok(());
+            }
+        }
+
+        let bucket: Option<fn(&mut Client) -> &mut TokenBucket> = match action {
+            Action::Move(..) => Some(|client| &mut client.moves),
+            Action::Shoot(..) => Some(|client| &mut client.shots),
+            _ => None,
+        };
+        if let Some(bucket) = bucket {
+            if !self.take_token(id, bucket, buf)? {
+                // a kicked client is not told anything more
+                if self.entities.get(id).is_some() {
+                    self.reject(id, action, RejectReason::RateLimited, buf)?;
+                }
+                return Ok(());
+            }
+        }
+
+        // the world is frozen while the results are shown
+        if self.phase == MatchPhase::PostMatch {
+            return self.reject(id, action, RejectReason::MatchOver, buf);
+        }
+
+        // cooldowns are measured from the moment the action comes in
+        self.update_world(buf)?;
+        let events = self.world.apply(action);
+        self.handle_events(events, buf);
+
+        Ok(())
+    }
+
+    /// Dead for longer than the news of it takes to reach the client
+    fn acted_while_dead(&self, id: u32) -> bool {
+        let dead = self.world.player(id).is_some_and(|player| player.dead);
+        let respawn_at = self.entities.get(id).and_then(|client| client.respawn_at);
+
+        match respawn_at {
+            Some(at) if dead => {
+                let dead_for = self
+                    .config
+                    .respawn_time
+                    .saturating_sub(at.saturating_duration_since(Instant::now()));
+                dead_for > DEAD_ACTION_GRACE
+            }
+            _ => false,
+        }
+    }
+
+    /// Records what the client did and punishes it once it reaches the violation limit. Bots
+    /// play by the rules and a client which is gone already has nothing more to answer for
+    fn violation(&mut self, id: u32, violation: Violation, buf: &mut [u8]) -> Result<(), ()> {
+        if !self.entities.is_remote(id) {
+            return Ok(());
+        }
+        let client = self.entities.get_mut(id).ok_or(())?;
+        client.violations = client.violations.saturating_add(1);
+        let violations = client.violations;
+        log_info!(
+            "Player: {id} {}, {violations} violations",
+            violation.description()
+        );
+        if violations < self.config.violation_limit {
+            return Ok(());
+        }
+
+        match self.config.punishment {
+            Punishment::Log => log_info!("Player: {id} is over the violation limit"),
+            Punishment::Kick => self
+                .kick(id, "Cheating", buf)
+                .map_err(|err| log_error!("{err}"))?,
+            Punishment::Ban => {
+                let ip = self.entities.addr(id).ok_or(())?.ip();
+                let reply = self.ban(ip, buf).map_err(|err| log_error!("{err}"))?;
+                log_info!("{reply}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Tells the player why the action did not happen, a move also gets the player put back
+    fn reject(
+        &self,
+        id: u32,
+        action: Action,
+        reason: RejectReason,
+        buf: &mut [u8],
+    ) -> Result<(), ()> {
+        let n = protocol::generate_action_rejected_payload(buf, action.kind(), reason)
+            .map_err(|_| log_error!("Could not generate action_rejected"))?;
+        let _ = self.entities.send(id, &buf[..n]);
+        if let Action::Move(..) = action {
+            let _ = self
+                .send_new_coords(id, buf)
+                .map_err(|err| log_error!("{err}"));
+        }
+
+        Ok(())
+    }
+
+    /// Takes a token from the bucket of the client, false if there was none and the packet has
+    /// to be dropped. A client which keeps sending dropped packets commits violations. Bots are
+    /// never limited
+    fn take_token(
+        &mut self,
+        id: u32,
+        bucket: fn(&mut Client) -> &mut TokenBucket,
+        buf: &mut [u8],
+    ) -> Result<bool, ()> {
+        if !self.entities.is_remote(id) {
+            return Ok(true);
+        }
+        let now = Instant::now();
+        let client = self.entities.get_mut(id).ok_or(())?;
+        if bucket(client).take(now) {
+            return Ok(true);
+        }
+
+        if !client.flood.take(now) {
+            self.violation(id, Violation::ImpossibleRate, buf)?;
+        }
+
+        Ok(false)
+    }
+
+    /// Turns what happened in the world into packets for the players concerned. An event which
+    /// fails is logged and skipped, the rest still reach the players
+    fn handle_events(&mut self, events: Vec<Event>, buf: &mut [u8]) {
+        for event in events {
+            if self.handle_event(event.clone(), buf).is_err() {
+                log_error!("Could not handle {event:?}");
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: Event, buf: &mut [u8]) -> Result<(), ()> {
+        match event {
+            Event::Moved { id, from, to } => {
+                self.notify_moved(id, from, to, buf)
+                    .map_err(|err| log_error!("{err}"))?;
+                self.touch_flags(id, buf)?;
+            }
+            Event::CellChanged(coords) => {
+                let block = self.world.arena.cell(coords).visible_block();
+                self.notify_cell_changed(types::MapCell::new(block, coords), buf)
+                    .map_err(|err| log_error!("{err}"))?;
+            }
+            Event::InventoryChanged(id) => self
+                .send_inventory(id, buf)
+                .map_err(|err| log_error!("{err}"))?,
+            Event::WeaponChanged(id) => self
+                .send_weapon_state(id, buf)
+                .map_err(|err| log_error!("{err}"))?,
+            Event::Healed { id, hp } => {
+                let n = protocol::generate_healed_payload(buf, hp)
+                    .map_err(|_| log_error!("Could not generate healed"))?;
+                let _ = self.entities.send(id, &buf[..n]);
+            }
+            Event::Hit {
+                id,
+                damage,
+                direction,
+            } => {
+                let n = protocol::generate_shoot_payload(buf, damage, direction)
+                    .map_err(|_| log_error!("Could not generate shoot"))?;
+                let _ = self.entities.send(id, &buf[..n]);
+            }
+            Event::Killed { id, by } => {
+                log_info!("Player: {id} died");
+                self.player_died(by, id, buf)?;
+            }
+            Event::ProjectileSpawned(projectile) => {
+                let n = protocol::generate_projectile_spawned_payload(buf, projectile)
+                    .map_err(|_| log_error!("Could not generate projectile_spawned"))?;
+                self.send_to_observers(&[projectile.coords], &buf[..n]);
+            }
+            // those who only saw it leave are told as well, so they stop drawing it
+            Event::ProjectileMoved { projectile, from } => {
+                let n = protocol::generate_projectile_moved_payload(buf, projectile)
+                    .map_err(|_| log_error!("Could not generate projectile_moved"))?;
+                self.send_to_observers(&[from, projectile.coords], &buf[..n]);
+            }
+            Event::ProjectileHit { id, coords } => {
+                let n = protocol::generate_projectile_hit_payload(buf, id, coords)
+                    .map_err(|_| log_error!("Could not generate projectile_hit"))?;
+                self.send_to_observers(&[coords], &buf[..n]);
+            }
+            Event::ShotFired {
+                shooter,
+                origin,
+                direction,
+                range,
+                hit,
+            } => {
+                let path = (0..range as i16)
+                    .filter_map(|distance| {
+                        origin.offset(
+                            direction.delta() * distance,
+                            self.world.arena.height,
+                            self.world.arena.width,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                let shot = protocol::ShotFired {
+                    shooter,
+                    origin,
+                    direction,
+                    range,
+                    hit,
+                };
+                let n = protocol::generate_shot_fired_payload(buf, shot)
+                    .map_err(|_| log_error!("Could not generate shot_fired"))?;
+                self.send_to_observers(&path, &buf[..n]);
+            }
+            Event::Rejected { action, reason } => {
+                log_error!("{action:?} rejected, err: {}", reason.message());
+                let n = protocol::generate_action_rejected_payload(buf, action.kind(), reason)
+                    .map_err(|_| log_error!("Could not generate action_rejected"))?;
+                let _ = self.entities.send(action.player(), &buf[..n]);
+                if let Action::Move(id, _) = action {
+                    let _ = self
+                        .send_new_coords(id, buf)
+                        .map_err(|err| log_error!("{err}"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends the player what it sees from the new cell and tells everyone around about the move
+    fn notify_moved(
+        &self,
+        id: u32,
+        prev_coords: Position,
+        coords: Position,
+        buf: &mut [u8],
+    ) -> Result<(), String> {
+        let player = self.world.player(id).ok_or("Player is gone")?;
+        let mut buf_move = [0; BUF_SIZE_64];
+        let n_move = protocol::generate_move_notify_payload(
+            &mut buf_move,
+            coords,
+            id,
+            player.name.clone(),
+            player.team,
+        )
+        .map_err(|_| "Error during generating payload move notify")?;
+        let mut buf_move_outside = [0; BUF_SIZE_8];
+        let n_move_outside =
+            protocol::generate_move_outside_radius_notify_payload(&mut buf_move_outside, id)
+                .map_err(|_| "Error during generating payload move outside radius")?;
+        // one step away from the previous coords, so this covers everyone who saw either cell
+        for other in self.world.players_within(coords, PLAYER_VIEW_RADIUS + 1) {
+            if other.id == id {
+                continue;
+            }
+
+            let sees_new = PREDICATE_CLIENT_INSIDE_RADIUS(other.coords, other.radius, coords);
+            let saw_prev = PREDICATE_CLIENT_INSIDE_RADIUS(other.coords, other.radius, prev_coords);
+
+            // send to other players new coords of this if in radius
+            if sees_new {
+                let _ = self.entities.send(other.id, &buf_move[..n_move]);
+            }
+
+            // sent to other players if player moved outside from their radius
+            if saw_prev && !sees_new {
+                let _ = self
+                    .entities
+                    .send(other.id, &buf_move_outside[..n_move_outside]);
+            }
+        }
+
+        self.send_new_coords(id, buf)
+    }
+
+    /// Tells the player where it stands and what it sees from there, along with the last of its
+    /// moves which has been handled. Also sent after a refused move, so the client takes back
+    /// the step it has already made on its side
+    fn send_new_coords(&self, id: u32, buf: &mut [u8]) -> Result<(), String> {
+        let player = self.world.player(id).ok_or("Player is gone")?;
+        let client = self.entities.get(id).ok_or("Client is gone")?;
+        let (coords, radius) = (player.coords, player.radius);
+        let n = protocol::generate_new_coords_payload(
+            buf,
+            coords,
+            self.world.visible_cells(coords, radius),
+            self.world.visible_players(coords, radius, id),
+            client.last_move,
+        )
+        .map_err(|_| "Error during generating payload for new coords")?;
+        let _ = self.entities.send(id, &buf[..n]);
+
+        Ok(())
+    }
+
+    /// Best players first, cut to what fits in a single packet
+    fn scoreboard(&self) -> Vec<Score> {
+        let mut scores = self
+            .entities
+            .iter()
+            .filter_map(|c| Some(c.score(self.world.player(c.id)?)))
+            .collect::<Vec<_>>();
+        scores.sort_by(|a, b| b.kills.cmp(&a.kills).then(a.deaths.cmp(&b.deaths)));
+        scores.truncate(SCOREBOARD_MAX_ROWS);
+
+        scores
+    }
+
+    fn chat(
+        &mut self,
+        id: u32,
+        channel: ChatChannel,
+        text: &str,
+        buf: &mut [u8],
+    ) -> Result<(), ()> {
+        let Some(text) = sanitize_chat(text) else {
+            return Ok(());
+        };
+        if !self.take_token(id, |client| &mut client.chat, buf)? {
+            log_error!("Client {id} is sending chat messages too fast");
+            return Ok(());
+        }
+        let (from, coords) = {
+            let sender = self.world.player(id).ok_or(())?;
+            ((sender.id, sender.name.clone()), sender.coords)
+        };
+
+        let n = protocol::generate_chat_message_payload(buf, from, channel, text)
+            .map_err(|_| log_error!("Could not generate chat_message"))?;
+        match channel {
+            ChatChannel::Global => self.entities.broadcast(&buf[..n]),
+            ChatChannel::Proximity => {
+                for p in self.world.players_within(coords, CHAT_PROXIMITY_RADIUS) {
+                    let _ = self.entities.send(p.id, &buf[..n]);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn player_died(&mut self, killer: u32, victim: u32, buf: &mut [u8]) -> Result<(), ()> {
+        self.entities.get_mut(killer).ok_or(())?.stats.record_kill();
+        let (killer_info, killer_team) = {
+            let killer = self.world.player(killer).ok_or(())?;
+            ((killer.id, killer.name.clone()), killer.team)
+        };
+        let respawn_in = min(self.config.respawn_time.as_secs(), u8::MAX as u64) as u8;
+        {
+            let victim = self.entities.get_mut(victim).ok_or(())?;
+            victim.respawn_at = Some(Instant::now() + self.config.respawn_time);
+            victim.killed_by = killer;
+            victim.stats.record_death();
+        }
+        let (id, name, coords) = {
+            let victim = self.world.player(victim).ok_or(())?;
+            (victim.id, victim.name.clone(), victim.coords)
+        };
+
+        let n = protocol::generate_player_died_payload(buf, killer_info.0, respawn_in)
+            .map_err(|_| log_error!("Could not generate player_died"))?;
+        let _ = self.entities.send(id, &buf[..n]);
+
+        let n = protocol::generate_other_player_died_payload(buf, id)
+            .map_err(|_| log_error!("Could not generate other_player_died"))?;
+        for p in self.world.players_within(coords, PLAYER_VIEW_RADIUS) {
+            if p.id != id && PREDICATE_CLIENT_INSIDE_RADIUS(p.coords, p.radius, coords) {
+                let _ = self.entities.send(p.id, &buf[..n]);
+            }
+        }
+
+        let n = protocol::generate_kill_feed_payload(buf, killer_info, (id, name))
+            .map_err(|_| log_error!("Could not generate kill_feed"))?;
+        self.entities.broadcast(&buf[..n]);
+
+        match (self.config.mode, killer_team) {
+            (GameMode::TeamDeathmatch, Some(team)) => {
+                self.team_scores.add_point(team);
+                self.broadcast_team_scores(buf)?;
+            }
+            (GameMode::CaptureTheFlag, _) => self.drop_flags(id, coords, buf)?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn broadcast_team_scores(&self, buf: &mut [u8]) -> Result<(), ()> {
+        let n = protocol::generate_team_scores_payload(buf, self.team_scores)
+            .map_err(|_| log_error!("Could not generate team_scores"))?;
+        self.entities.broadcast(&buf[..n]);
+
+        Ok(())
+    }
+
+    /// Moves a flag on or off the map and tells everybody about it
+    fn set_flag_state(&mut self, index: usize, state: FlagState, buf: &mut [u8]) -> Result<(), ()> {
+        let flag = &mut self.flags[index];
+        let old_coords = flag.coords();
+        flag.state = state;
+        let (team, new_coords, status) = (flag.team, flag.coords(), flag.status());
+
+        let mut changed = vec![];
+        if let Some(coords) = old_coords {
+            let cell = self.world.arena.cell_mut(coords);
+            cell.flag = None;
+            changed.push(types::MapCell::new(cell.visible_block(), coords));
+        }
+        if let Some(coords) = new_coords {
+            let cell = self.world.arena.cell_mut(coords);
+            cell.flag = Some(team);
+            changed.push(types::MapCell::new(cell.visible_block(), coords));
+        }
+        for cell in changed {
+            self.notify_cell_changed(cell, buf)
+                .map_err(|err| log_error!("{err}"))?;
+        }
+
+        let n = protocol::generate_flag_status_payload(buf, status)
+            .map_err(|_| log_error!("Could not generate flag_status"))?;
+        self.entities.broadcast(&buf[..n]);
+
+        Ok(())
+    }
+
+    /// Capture the flag rules for a player who has just stepped on a new cell
+    fn touch_flags(&mut self, id: u32, buf: &mut [u8]) -> Result<(), ()> {
+        let player = self.world.player(id).ok_or(())?;
+        let (coords, team) = (player.coords, player.team);
+        let Some(team) = team else {
+            return Ok(());
+        };
+
+        for index in 0..self.flags.len() {
+            let flag = &self.flags[index];
+            if flag.coords() != Some(coords) {
+                continue;
+            }
+
+            if flag.team != team {
+                log_info!("Player: {id} took the {} flag", flag.team.name());
+                self.set_flag_state(index, FlagState::Carried(id), buf)?;
+            } else if flag.state != FlagState::AtBase {
+                log_info!("Player: {id} returned the {} flag", flag.team.name());
+                self.set_flag_state(index, FlagState::AtBase, buf)?;
+            }
+        }
+
+        // bringing the enemy flag to our own one while it is safe at home scores
+        let at_home = self
+            .flags
+            .iter()
+            .any(|f| f.team == team && f.state == FlagState::AtBase && f.home == coords);
+        let carried = self
+            .flags
+            .iter()
+            .position(|f| f.state == FlagState::Carried(id));
+        if let (true, Some(index)) = (at_home, carried) {
+            log_info!(
+                "Player: {id} captured the {} flag",
+                self.flags[index].team.name()
+            );
+            self.team_scores.add_point(team);
+            self.set_flag_state(index, FlagState::AtBase, buf)?;
+            self.broadcast_team_scores(buf)?;
+        }
+
+        Ok(())
+    }
+
+    /// Leaves every flag the player carries where they stood
+    fn drop_flags(&mut self, id: u32, coords: Position, buf: &mut [u8]) -> Result<(), ()> {
+        for index in 0..self.flags.len() {
+            if self.flags[index].state == FlagState::Carried(id) {
+                self.set_flag_state(index, FlagState::Dropped(coords), buf)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lets the world catch up with the clock, finishing the reloads which are done by now
+    fn update_world(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+        let events = self.world.tick(Instant::now());
+        self.handle_events(events, buf);
+
+        Ok(())
+    }
+
+    fn match_state(&self) -> MatchState {
+        let seconds_left = self.phase_ends_at.map_or(0, |at| {
+            at.saturating_duration_since(Instant::now())
+                .as_secs_f32()
+                .ceil() as u64
+        });
 
-        match direction {
-            Direction::Down | Direction::Right => {
-                let map = self.map_ref.read().unwrap();
-                for i in point_from.0..=point_to.0 {
-                    for j in point_from.1..=point_to.1 {
-                        if let Some(ref enemy) = map.coords[i as usize][j as usize].client {
-                            let mut enemy = enemy.write().unwrap();
-                            enemy.hp = enemy.hp.checked_sub(damage).unwrap_or(0);
-
-                            if enemy.hp == 0 {
-                                log_info!("Player: {} died", enemy.id);
-                                let n =
-                                    protocol::generate_player_died_payload(buf, self.id).unwrap();
-                                let _ = enemy.write(&buf[..n]);
-                                return;
-                            }
-
-                            let n =
-                                protocol::generate_shoot_payload(buf, damage, direction).unwrap();
-                            let _ = enemy.write(&buf[..n]);
-                            return;
-                        }
-                    }
-                }
-            }
-            Direction::Up | Direction::Left => {
-                let map = self.map_ref.read().unwrap();
-                for i in (point_to.0..=point_from.0).rev() {
-                    for j in (point_to.1..=point_from.1).rev() {
-                        if let Some(ref enemy) = map.coords[i as usize][j as usize].client {
-                            let mut enemy = enemy.write().unwrap();
-                            enemy.hp = enemy.hp.checked_sub(damage).unwrap_or(0);
-
-                            if enemy.hp == 0 {
-                                log_info!("Player: {} died", enemy.id);
-                                let n =
-                                    protocol::generate_player_died_payload(buf, self.id).unwrap();
-                                let _ = enemy.write(&buf[..n]);
-                                return;
-                            }
-
-                            let n =
-                                protocol::generate_shoot_payload(buf, damage, direction).unwrap();
-                            let _ = enemy.write(&buf[..n]);
-                            return;
-                        }
-                    }
-                }
-            }
+        MatchState {
+            phase: self.phase,
+            seconds_left: min(seconds_left, u16::MAX as u64) as u16,
+            players: min(self.entities.len(), u8::MAX as usize) as u8,
+            min_players: min(self.config.min_players, u8::MAX as usize) as u8,
         }
     }
 
-    fn do_move(
+    fn broadcast_match_state(&self, buf: &mut [u8]) -> Result<(), ()> {
+        let n = protocol::generate_match_state_payload(buf, self.match_state())
+            .map_err(|_| log_error!("Could not generate match_state"))?;
+        self.entities.broadcast(&buf[..n]);
+
+        Ok(())
+    }
+
+    fn set_phase(
         &mut self,
-        direction: Direction,
-        clients: &HashMap<SocketAddr, Arc<RwLock<Client>>>,
+        phase: MatchPhase,
+        duration: Option<Duration>,
         buf: &mut [u8],
-    ) -> Result<(), String> {
-        let prev_coords = self.coords;
-        let (new_x, new_y) = match direction {
-            Direction::Up => (
-                self.coords.0.checked_sub(1).ok_or("Cannot move up")?,
-                self.coords.1,
-            ),
-            Direction::Down => (self.coords.0 + 1, self.coords.1),
-            Direction::Left => (
-                self.coords.0,
-                self.coords.1.checked_sub(1).ok_or("Cannot move left")?,
-            ),
-            Direction::Right => (self.coords.0, self.coords.1 + 1),
-        };
+    ) -> Result<(), ()> {
+        log_info!("Match phase: {phase:?}");
+        self.phase = phase;
+        self.phase_ends_at = duration.map(|duration| Instant::now() + duration);
 
-        {
-            let map = self.map_ref.read().unwrap();
+        self.broadcast_match_state(buf)
+    }
 
-            // Check map bounds
-            if new_x >= map.height as u16 || new_y >= map.width as u16 {
-                return Err("New position is outside the map".to_string());
-            }
+    /// Moves the match along: lobby -> warmup -> live -> post match -> lobby
+    fn update_match(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+        let enough_players = self.entities.len() >= self.config.min_players;
+        let time_up = self.phase_ends_at.is_some_and(|at| at <= Instant::now());
 
-            // Check if new cell is occupied
-            if map
-                .coords
-                .get(new_x as usize)
-                .and_then(|row| row.get(new_y as usize))
-                .and_then(|cell| cell.client.as_ref())
-                .is_some()
-            {
-                return Err("Cell is occupied".to_string());
+        match self.phase {
+            MatchPhase::Lobby if enough_players => {
+                self.set_phase(MatchPhase::Warmup, Some(self.config.warmup_time), buf)?;
             }
-        }
-
-        // Perform the move
-        {
-            let mut map = self.map_ref.write().unwrap();
-            let current_cell = std::mem::replace(
-                &mut map.coords[self.coords.0 as usize][self.coords.1 as usize].client,
-                None,
-            );
-            map.coords[new_x as usize][new_y as usize].client = current_cell;
-        }
-
-        self.coords = (new_x, new_y);
-
-        let mut buf_move = [0; BUF_SIZE_16];
-        let n_move = protocol::generate_move_notify_payload(&mut buf_move, self.coords, self.id)
-            .map_err(|_| "Error during generating payload move notify")?;
-        let mut buf_move_outside = [0; BUF_SIZE_8];
-        let n_move_outside =
-            protocol::generate_move_outside_radius_notify_payload(&mut buf_move_outside, self.id)
-                .map_err(|_| "Error during generating payload move outside radius")?;
-        let mut visible_players_to_client = vec![];
-        for c in clients.values() {
-            {
-                if let Err(_) = c.try_read() {
-                    continue;
-                }
-                //if c.id == self.id {
-                //    continue;
-                //}
+            MatchPhase::Warmup if !enough_players => {
+                self.set_phase(MatchPhase::Lobby, None, buf)?;
             }
-            let mut c = c.write().unwrap();
-
-            if PREDICATE_CLIENT_INSIDE_RADIUS(self.coords, self.radius, c.coords) {
-                visible_players_to_client.push(Player::new(c.id, c.coords))
+            MatchPhase::Warmup if time_up => {
+                self.reset_match(None, buf)?;
+                self.set_phase(MatchPhase::Live, Some(self.config.time_limit), buf)?;
             }
-
-            // send to other players new coords of this if in radius
-            if PREDICATE_CLIENT_INSIDE_RADIUS(c.coords, c.radius, self.coords) {
-                let _ = c.write(&buf_move[..n_move]);
+            MatchPhase::Live if self.entities.is_empty() => {
+                self.reset_match(Some(utils::generate_map()), buf)?;
+                self.set_phase(MatchPhase::Lobby, None, buf)?;
             }
-
-            // sent to other players if player moved outside from their radius
-            if PREDICATE_CLIENT_INSIDE_RADIUS(c.coords, c.radius, prev_coords)
-                && !PREDICATE_CLIENT_INSIDE_RADIUS(c.coords, c.radius, self.coords)
-            {
-                let _ = c.write(&buf_move_outside[..n_move_outside]);
+            MatchPhase::Live => {
+                if let Some(winner) = self.winner(time_up) {
+                    self.end_match(winner, buf)?;
+                }
+            }
+            MatchPhase::PostMatch if time_up => {
+                self.reset_match(Some(utils::generate_map()), buf)?;
+                self.set_phase(MatchPhase::Lobby, None, buf)?;
             }
+            _ => {}
         }
-        // send new coords to player
-        let new_visiple_coord = visible_map(&self.map_ref, self.coords, self.radius);
-        let n = protocol::generate_new_coords_payload(
-            buf,
-            self.coords,
-            new_visiple_coord,
-            visible_players_to_client,
-        )
-        .map_err(|_| "Error during generating payload for new coords")?;
-        let _ = self.write(&buf[..n]);
 
         Ok(())
     }
 
-    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
-        self.conn.deref().write(buf)
-    }
-}
+    /// None while the match goes on, once the time is up the leader wins
+    fn winner(&self, time_up: bool) -> Option<Winner> {
+        let limit = self.config.score_limit();
 
-struct MapCell {
-    block: Block,
-    client: Option<Arc<RwLock<Client>>>,
-}
+        if self.config.mode.has_teams() {
+            let TeamScores { red, blue } = self.team_scores;
+            if !time_up && red < limit && blue < limit {
+                return None;
+            }
 
-impl Default for MapCell {
-    fn default() -> Self {
-        Self {
-            block: Block::Grass,
-            client: None,
+            return Some(match red.cmp(&blue) {
+                Ordering::Greater => Winner::Team(Team::Red),
+                Ordering::Less => Winner::Team(Team::Blue),
+                Ordering::Equal => Winner::Draw,
+            });
+        }
+
+        let scores = self.scoreboard();
+        let Some(best) = scores.first() else {
+            return time_up.then_some(Winner::Draw);
+        };
+        if !time_up && best.kills < limit {
+            return None;
+        }
+
+        match scores.get(1) {
+            Some(second) if second.kills == best.kills => Some(Winner::Draw),
+            _ => Some(Winner::Player(best.id)),
         }
     }
-}
 
-struct ServerMap {
-    height: usize,
-    width: usize,
-    coords: Vec<Vec<MapCell>>,
-}
+    fn end_match(&mut self, winner: Winner, buf: &mut [u8]) -> Result<(), ()> {
+        log_info!("Match over, winner: {winner:?}");
+        self.set_phase(MatchPhase::PostMatch, Some(POST_MATCH_TIME), buf)?;
+
+        let results = MatchResults {
+            winner,
+            team_scores: self.team_scores,
+            scores: self.scoreboard(),
+        };
+        let n = protocol::generate_match_results_payload(buf, results)
+            .map_err(|_| log_error!("Could not generate match_results"))?;
+        self.entities.broadcast(&buf[..n]);
+
+        Ok(())
+    }
 
-impl ServerMap {
-    fn from_map(map: &Map) -> ServerMap {
-        let &Map { height, width, .. } = map;
-        let coords = &map.coords;
-        let mut sm_coords = Vec::with_capacity(height);
-        for _ in 0..height {
-            sm_coords.push(Vec::with_capacity(width));
+    /// Fresh scores and every player respawned, on a new map if one is given
+    fn reset_match(&mut self, map: Option<Map>, buf: &mut [u8]) -> Result<(), ()> {
+        let new_map = map.is_some();
+        self.team_scores = TeamScores::default();
+        for c in self.entities.iter_mut() {
+            c.stats = Stats::default();
+            // whoever finds no free cell below waits like the dead do
+            c.respawn_at = Some(Instant::now());
+        }
+        // a new map may not even have the cells the bots were heading to
+        for bot in self.bots.iter_mut() {
+            bot.wander_to = None;
         }
 
-        for (i, row) in sm_coords.iter_mut().enumerate() {
-            for j in 0..row.capacity() {
-                row.push(MapCell {
-                    block: coords[i][j],
-                    client: None,
-                });
+        if let Some(map) = map {
+            let (arena, item_spawns, flags) = new_arena(&self.config, &map);
+            // everybody is placed again by the respawn
+            self.world.replace_arena(arena);
+            self.item_spawns = item_spawns;
+            self.flags = flags;
+        } else {
+            for index in 0..self.flags.len() {
+                if self.flags[index].state != FlagState::AtBase {
+                    self.set_flag_state(index, FlagState::AtBase, buf)?;
+                }
             }
         }
 
-        ServerMap {
-            height,
-            width,
-            coords: sm_coords,
+        for id in self.entities.ids() {
+            self.respawn(id, buf)?;
         }
-    }
-}
-
-struct Server {
-    clients: HashMap<SocketAddr, Arc<RwLock<Client>>>,
-    id_counter: u32,
-    map: Arc<RwLock<ServerMap>>,
-}
 
-impl Server {
-    fn new() -> Self {
-        let map = ServerMap::from_map(&utils::generate_map());
-        Self {
-            map: Arc::new(RwLock::new(map)),
-            id_counter: 0,
-            clients: HashMap::new(),
+        if self.config.mode.has_teams() {
+            self.broadcast_team_scores(buf)?;
+        }
+        if new_map {
+            for flag in &self.flags {
+                let n = protocol::generate_flag_status_payload(buf, flag.status())
+                    .map_err(|_| log_error!("Could not generate flag_status"))?;
+                self.entities.broadcast(&buf[..n]);
+            }
         }
-    }
 
-    fn client_connected(
-        &mut self,
-        buf: &mut [u8],
-        addr: SocketAddr,
-        stream: Arc<TcpStream>,
-    ) -> Result<(), ()> {
-        log_info!("Client {addr} connected");
+        Ok(())
+    }
 
-        let client = Client::new_from_conn(stream, &mut self.id_counter, &self.map);
+    /// Players who are not bots, including those waiting to be resumed
+    fn humans(&self) -> usize {
+        self.entities.len() - self.bots.len()
+    }
 
-        let players_inside_radius = self
-            .clients
-            .values()
-            .filter(|&c| {
-                utils::is_inside_circle(client.coords, client.radius, c.read().unwrap().coords)
-            })
-            .map(|c| Player::new(c.read().unwrap().id, c.read().unwrap().coords))
-            .collect::<Vec<_>>();
+    /// Keeps the server filled up to the configured bot count, bots leave as humans join
+    fn balance_bots(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+        let wanted = self.config.bots.saturating_sub(self.humans());
+
+        while self.bots.len() < wanted {
+            self.bot_counter = self.bot_counter.wrapping_add(1);
+            // real peers never have the unspecified address, so there is no clash with them
+            let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, self.bot_counter));
+            self.bots.push(Bot {
+                addr,
+                next_think_at: Instant::now(),
+                wander_to: None,
+            });
+            self.client_joined(buf, addr, Connection::Bot, BOT_NAME)?;
+        }
+        while self.bots.len() > wanted {
+            if let Some(id) = self.bots.pop().and_then(|bot| self.entities.id(bot.addr)) {
+                self.remove_client(id, buf)?;
+            }
+        }
 
-        let visible_coords = visible_map(&self.map, client.coords, client.radius);
-        let n = protocol::generate_initial_payload(
-            buf,
-            client.id,
-            client.coords,
-            client.radius,
-            client.hp,
-            client.weapon.range,
-            visible_coords,
-            players_inside_radius,
-        )
-        .map_err(|_| log_error!("Could not generate payload"))?;
+        Ok(())
+    }
 
-        client
-            .conn
-            .deref()
-            .write(&buf[..n])
-            .map_err(|err| log_error!("Could not write to client: {addr}, {err}"))?;
+    /// Bots act through the same packets as the players do
+    fn update_bots(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+        let now = Instant::now();
 
-        let players_seeing_client = self.clients.iter().filter(|(_, c)| {
-            let c = c.read().unwrap();
-            utils::is_inside_circle(c.coords, c.radius, client.coords)
-        });
+        for index in 0..self.bots.len() {
+            if self.bots[index].next_think_at > now {
+                continue;
+            }
+            self.bots[index].next_think_at = now + self.config.bot_difficulty.think_interval();
 
-        for (&other_addr, other_client) in players_seeing_client {
-            log_info!(
-                "Sending move notification to player with id: {}",
-                other_client.read().unwrap().id
-            );
-            let n = protocol::generate_move_notify_payload(buf, client.coords, client.id)
-                .map_err(|_| ())?;
-            other_client
-                .read()
-                .unwrap()
-                .conn
-                .deref()
-                .write(&buf[..n])
-                .map_err(|err| {
-                    log_error!("Could not notify client {other_addr} about the move: {err}")
-                })?;
-        }
-
-        let (x, y) = (client.coords.0 as usize, client.coords.1 as usize);
-        let client = Arc::new(RwLock::new(client));
-        self.map
-            .write()
-            .unwrap()
-            .coords
-            .get_mut(x)
-            .and_then(|row| row.get_mut(y))
-            .map(|col| col.client = Some(Arc::clone(&client)));
-        self.clients.insert(addr, client);
+            let Some(action) = self.bot_action(index) else {
+                continue;
+            };
+            self.client_sent(self.bots[index].addr, vec![Packet::Client(action)], buf);
+        }
 
         Ok(())
     }
 
-    fn client_disconnected(&mut self, addr: SocketAddr, buf: &mut [u8]) -> Result<(), ()> {
-        log_info!("Client {addr} disconnected");
+    /// Shoots the closest enemy it can see past the walls when it is in a lane of the weapon,
+    /// walks towards it otherwise and wanders around the map while there is nobody to chase
+    fn bot_action(&mut self, index: usize) -> Option<ClientPacket> {
+        if self.phase == MatchPhase::PostMatch {
+            return None;
+        }
+
+        let (id, coords, team, weapon) = {
+            let id = self.entities.id(self.bots[index].addr)?;
+            let bot = self.world.player(id)?;
+            if bot.dead {
+                return None;
+            }
+            let weapon = bot.current_weapon();
+
+            (
+                bot.id,
+                bot.coords,
+                bot.team,
+                (
+                    weapon.kind.spec(),
+                    weapon.ammo,
+                    weapon.reloading,
+                    weapon.has_reserve(),
+                ),
+            )
+        };
+        let (spec, ammo, reloading, has_reserve) = weapon;
+        if reloading {
+            return None;
+        }
+        if ammo == 0 {
+            return Some(match has_reserve {
+                true => ClientPacket::Reload,
+                false => ClientPacket::SwitchWeapon(WeaponKind::Pistol),
+            });
+        }
 
-        let (id, coords) = {
-            let removed = self
-                .clients
-                .remove(&addr)
-                .ok_or(())
-                .map_err(|_| log_error!("Did not found client in hashmap on disconnect"))?;
-            let removed = removed.read().unwrap();
+        let sight = self.config.bot_difficulty.sight();
+        let arena = &self.world.arena;
+        let target = self
+            .world
+            .players_within(coords, sight)
+            .filter(|p| p.id != id && !p.dead && (team.is_none() || p.team != team))
+            .filter(|p| arena.line_of_sight(coords, p.coords))
+            .min_by_key(|p| coords.manhattan_distance(p.coords))
+            .map(|p| p.coords);
+
+        if let Some(target) = target {
+            return match aim(arena, coords, target, spec) {
+                Some(direction) => Some(ClientPacket::Shoot(direction)),
+                None => next_step(arena, coords, target).map(|d| ClientPacket::Move(0, d)),
+            };
+        }
 
-            (removed.id, removed.coords)
+        let bot = &mut self.bots[index];
+        let wander_to = match bot.wander_to {
+            Some(wander_to) if wander_to != coords => wander_to,
+            _ => utils::generate_random_position(arena.height, arena.width),
         };
+        let step = next_step(arena, coords, wander_to);
+        // unreachable, pick another spot next time
+        bot.wander_to = step.is_some().then_some(wander_to);
 
-        self.map
-            .write()
-            .unwrap()
-            .coords
-            .get_mut(coords.0 as usize)
-            .and_then(|row| row.get_mut(coords.1 as usize))
-            .map(|mc| mc.client = None);
+        step.map(|direction| ClientPacket::Move(0, direction))
+    }
 
-        let n = protocol::generate_player_disconnected(buf, id)
-            .map_err(|_| log_error!("Could not generate player_disconnected"))?;
+    /// Puts items back on their spawn points once the respawn time after a pickup has passed
+    fn spawn_items(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+        let now = Instant::now();
+        let mut spawned = vec![];
+        for spawn in self.item_spawns.iter_mut() {
+            let cell = self.world.arena.cell_mut(spawn.coords);
+            match spawn.respawn_at {
+                // picked up since the last check
+                None if cell.item.is_none() => {
+                    spawn.respawn_at = Some(now + self.config.item_respawn_time);
+                }
+                // wait for whoever stands on the spawn point to leave
+                Some(at) if at <= now && cell.occupant().is_none() => {
+                    cell.item = Some(spawn.kind);
+                    spawn.respawn_at = None;
+                    spawned.push(types::MapCell::new(cell.visible_block(), spawn.coords));
+                }
+                _ => {}
+            }
+        }
 
-        for c in self.clients.values() {
-            let _ = c
-                .write()
-                .unwrap()
-                .write(&mut buf[..n]);
+        for cell in spawned {
+            self.notify_cell_changed(cell, buf)
+                .map_err(|err| log_error!("{err}"))?;
         }
 
         Ok(())
     }
 
-    fn client_wrote(&mut self, addr: SocketAddr, bytes: &[u8], buf: &mut [u8]) -> Result<(), ()> {
-        let client = self.clients.get(&addr).ok_or(()).map_err(|_| ())?;
-        let (packet, _) = Packet::deserialize(bytes)
-            .map_err(|_| log_error!("Could not deserialize packet from client"))?;
+    fn respawn_dead_players(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+        let now = Instant::now();
+        let ready = self
+            .entities
+            .iter()
+            .filter(|c| c.respawn_at.is_some_and(|at| at <= now))
+            .map(|c| c.id)
+            .collect::<Vec<_>>();
 
-        match packet {
-            Packet::Client(cp) => match cp {
-                ClientPacket::Shoot(direction) => {
-                    client.read().unwrap().do_shoot(direction, buf);
-                }
-                ClientPacket::Move(direction) => {
-                    log_info!("Got Move client packet with direction: {:?}", direction);
-                    if let Err(err) = client
-                        .write()
-                        .unwrap()
-                        .do_move(direction, &self.clients, buf)
-                    {
-                        log_error!("Client {addr} can not move, err: {err}");
-                    }
-                }
-            },
-            _ => return Err(()),
+        for id in ready {
+            self.respawn(id, buf)?;
+        }
+
+        Ok(())
+    }
+
+    fn respawn(&mut self, id: u32, buf: &mut [u8]) -> Result<(), ()> {
+        let team = self.world.player(id).ok_or(())?.team;
+        // still dead, the next tick tries again
+        let Some(coords) = self.world.arena.random_free_coords(team) else {
+            log_error!("No free cell left to respawn player: {id}");
+            return Ok(());
+        };
+        self.entities.get_mut(id).ok_or(())?.respawn_at = None;
+        self.world.respawn(id, coords);
+        let player = self.world.player(id).ok_or(())?;
+        let (name, radius) = (player.name.clone(), player.radius);
+        log_info!("Player: {id} respawned");
+
+        let players = self.world.visible_players(coords, radius, id);
+        let visible_coords = self.world.visible_cells(coords, radius);
+        let n =
+            protocol::generate_respawned_payload(buf, coords, PLAYER_HP, visible_coords, players)
+                .map_err(|_| log_error!("Could not generate respawned"))?;
+        let _ = self.entities.send(id, &buf[..n]);
+        self.send_inventory(id, buf)
+            .map_err(|err| log_error!("{err}"))?;
+        self.send_weapon_state(id, buf)
+            .map_err(|err| log_error!("{err}"))?;
+
+        let n =
+            protocol::generate_move_notify_payload(buf, coords, id, name, team).map_err(|_| ())?;
+        for p in self.world.players_within(coords, PLAYER_VIEW_RADIUS) {
+            if p.id != id && PREDICATE_CLIENT_INSIDE_RADIUS(p.coords, p.radius, coords) {
+                let _ = self.entities.send(p.id, &buf[..n]);
+            }
         }
 
         Ok(())
     }
 }
 
-fn server(events: Receiver<ClientEvent>) -> Result<(), ()> {
-    let mut server = Server::new();
-    let mut buf = [0; BUF_SIZE_512];
+type TickStep = fn(&mut Server, &mut [u8]) -> Result<(), ()>;
+
+/// What the server does every tick after handling the event, in order
+const TICK_STEPS: [(&str, TickStep); 8] = [
+    ("heartbeat", Server::heartbeat),
+    ("expire_sessions", Server::expire_sessions),
+    ("respawn_dead_players", Server::respawn_dead_players),
+    ("update_world", Server::update_world),
+    ("spawn_items", Server::spawn_items),
+    ("update_match", Server::update_match),
+    ("balance_bots", Server::balance_bots),
+    ("update_bots", Server::update_bots),
+];
+
+fn server(events: Receiver<ClientEvent>, config: Config, shutdown: &AtomicBool) -> Result<(), ()> {
+    let mut server = Server::new(config);
+    let mut buf = [0; BUF_SIZE_2048];
 
     loop {
-        match events.recv_timeout(Duration::from_millis(200)) {
+        if shutdown.load(AtomicOrdering::Relaxed) || server.shutdown_requested {
+            return server.shutdown(SHUTDOWN_REASON, &mut buf);
+        }
+
+        match events.recv_timeout(TICK) {
             Ok(msg) => match msg {
-                ClientEvent::Connect { addr, stream } => {
-                    server.client_connected(&mut buf, addr, stream)?
+                ClientEvent::Connect { addr, stream } => server.client_connected(addr, stream),
+                ClientEvent::Disconnect { addr } => server.client_disconnected(addr),
+                ClientEvent::Read { addr, packets } => server.client_sent(addr, packets, &mut buf),
+                ClientEvent::Garbage { addr } => {
+                    log_error!("Client {addr} sent something which is not a packet");
+                    server.client_failed(addr);
+                }
+                // the reading thread is gone, so nothing more comes from this connection
+                ClientEvent::Error { addr, err } => {
+                    log_error!("Client error: {}, {}", addr, err);
+                    server.client_disconnected(addr);
                 }
-                ClientEvent::Disconnect { addr } => server.client_disconnected(addr, &mut buf)?,
-                ClientEvent::Read { addr, bytes } => server.client_wrote(addr, &bytes, &mut buf)?,
-                ClientEvent::Error { addr, err } => log_error!("Client error: {}, {}", addr, err),
+                ClientEvent::Console { line } => println!("{}", server.admin(&line, &mut buf)),
             },
             Err(RecvTimeoutError::Timeout) => {}
             Err(RecvTimeoutError::Disconnected) => {
@@ -499,6 +2463,13 @@ fn server(events: Receiver<ClientEvent>) -> Result<(), ()> {
                 return Err(());
             }
         }
+
+        // a failed step is only cut short until the next tick
+        for (name, step) in TICK_STEPS {
+            if step(&mut server, &mut buf).is_err() {
+                log_error!("Tick step {name} failed");
+            }
+        }
     }
 }
 
@@ -509,6 +2480,8 @@ fn client(stream: Arc<TcpStream>, addr: SocketAddr, events: Sender<ClientEvent>)
     });
 
     let mut buf = [0; BUF_SIZE_512];
+    // a packet split over two reads waits here for its rest
+    let mut partial = Vec::new();
     loop {
         match stream.as_ref().read(&mut buf) {
             Ok(0) => {
@@ -518,10 +2491,21 @@ fn client(stream: Arc<TcpStream>, addr: SocketAddr, events: Sender<ClientEvent>)
                 break;
             }
             Ok(n) => {
-                let bytes = buf[..n].into();
-                events
-                    .send(ClientEvent::Read { addr, bytes })
-                    .expect("Send new message");
+                partial.extend_from_slice(&buf[..n]);
+                let packets = take_packets(&mut partial);
+                if !packets.is_empty() {
+                    events
+                        .send(ClientEvent::Read { addr, packets })
+                        .expect("Send new message");
+                }
+
+                // no packet is this long, what is left can never become one
+                if partial.len() > BUF_SIZE_2048 {
+                    events
+                        .send(ClientEvent::Garbage { addr })
+                        .expect("Send client sent garbage");
+                    break;
+                }
             }
             Err(err) => {
                 events
@@ -536,15 +2520,123 @@ fn client(stream: Arc<TcpStream>, addr: SocketAddr, events: Sender<ClientEvent>)
 }
 
 fn main() -> Result<(), ()> {
+    let config = Config::from_args(std::env::args().skip(1))?;
     let address = format!("{}:{}", constants::ALL_HOSTS, constants::PORT);
     let listener = TcpListener::bind(&address).map_err(|err| {
         log_error!("Could not bing {}: {}", address, err);
     })?;
     log_info!("Started server at {address}");
 
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for &signal in TERM_SIGNALS {
+        // a second signal ends the process right away, in case shutting down gets stuck
+        flag::register_conditional_shutdown(signal, 1, Arc::clone(&shutdown))
+            .and_then(|_| flag::register(signal, Arc::clone(&shutdown)))
+            .map_err(|err| log_error!("Could not register signal handler: {err}"))?;
+    }
+
     let (events_sender, events_receiver) = channel();
-    thread::spawn(|| server(events_receiver));
+    let console_sender = events_sender.clone();
+    thread::spawn(move || console(console_sender));
+    thread::spawn(move || accept(listener, events_sender));
+
+    // the accepting thread goes down with the process once the game is over
+    server(events_receiver, config, &shutdown)
+}
+
+/// Hands the lines typed into the server to the game, quietly gives up without a terminal
+fn console(events_sender: Sender<ClientEvent>) {
+    for line in io::stdin().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if events_sender.send(ClientEvent::Console { line }).is_err() {
+            break;
+        }
+    }
+}
+
+/// Splits the answer to an admin command into AdminReply packets
+fn send_admin_reply(reply: &str, buf: &mut [u8], mut send: impl FnMut(&[u8])) {
+    for line in reply.lines() {
+        // strings on the wire are at most 255 bytes long
+        let mut end = min(line.len(), u8::MAX as usize);
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        if let Ok(n) = protocol::generate_admin_reply_payload(buf, line[..end].to_string()) {
+            send(&buf[..n]);
+        }
+    }
+}
+
+/// Takes as long for every password of the same length, so the time of the answer does not tell
+/// how much of a guess was right
+fn passwords_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// One address per line, # starts a comment. A missing file is no bans yet
+fn load_bans(path: &Path) -> HashSet<IpAddr> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return HashSet::new(),
+        Err(err) => {
+            log_error!("Could not read the bans from {}: {err}", path.display());
+            return HashSet::new();
+        }
+    };
+
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| {
+            line.parse()
+                .map_err(|_| log_error!("Not an address in {}: {line}", path.display()))
+                .ok()
+        })
+        .collect()
+}
+
+/// Reads <name>.txt from the maps dir, made sure it can be played on
+fn load_map(dir: &Path, name: &str) -> Result<Map, String> {
+    // keeps the name from walking out of the maps dir
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err("Map names are made of letters, digits, _ and -".to_string());
+    }
+
+    let path = dir.join(format!("{name}.txt"));
+    let map = fs::read_to_string(&path)
+        .map_err(|err| format!("Could not read {}: {err}", path.display()))?
+        .parse::<Map>()
+        .map_err(|err| format!("Map {name} is broken: {err}"))?;
+
+    // flags stand one cell in from the sides and each team spawns on its own half
+    let arena = Arena::from_map(&map);
+    let half_has_room = |team| {
+        (0..map.height).any(|row| {
+            (0..map.width).any(|col| {
+                arena.is_team_side(Some(team), col as u16) && !map.coords[row][col].is_blocking()
+            })
+        })
+    };
+    if map.width < 4 || map.height < 3 || !Team::ALL.into_iter().all(half_has_room) {
+        return Err(format!("Map {name} has no room to play"));
+    }
+
+    Ok(map)
+}
 
+fn accept(listener: TcpListener, events_sender: Sender<ClientEvent>) {
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => match stream.peer_addr() {
@@ -558,43 +2650,578 @@ fn main() -> Result<(), ()> {
             Err(err) => log_error!("Could not accept connection: {}", err),
         }
     }
+}
 
-    Ok(())
+/// Takes the packets which have arrived whole off the start of the bytes
+fn take_packets(bytes: &mut Vec<u8>) -> Vec<Packet> {
+    let mut packets = vec![];
+    let mut complete = 0;
+    while let Ok((packet, size)) = Packet::deserialize(&bytes[complete..]) {
+        packets.push(packet);
+        complete += size;
+    }
+    bytes.drain(..complete);
+
+    packets
+}
+
+fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+        .take(constants::MAX_NAME_LEN)
+        .collect()
+}
+
+/// Strips control characters and caps the length, None if nothing is left to send
+fn sanitize_chat(text: &str) -> Option<String> {
+    let text = text
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(constants::MAX_CHAT_LEN)
+        .collect::<String>();
+    let text = text.trim();
+
+    (!text.is_empty()).then(|| text.to_string())
 }
 
-fn visible_map(map: &Arc<RwLock<ServerMap>>, coords: Coords, radius: u8) -> Vec<types::MapCell> {
-    let map = map.read().unwrap();
+/// Arena of the map with its item spawn points and flags
+fn new_arena(config: &Config, map: &Map) -> (Arena, Vec<ItemSpawn>, Vec<Flag>) {
+    let mut arena = Arena::from_map(map);
+    let now = Instant::now();
+    let item_spawns = arena
+        .random_distinct_coords(config.item_spawns)
+        .into_iter()
+        .filter(|&coords| !arena.cell(coords).block.is_blocking())
+        .zip(ITEM_SPAWN_KINDS.iter().cycle())
+        .map(|(coords, &kind)| ItemSpawn {
+            coords,
+            kind,
+            respawn_at: Some(now),
+        })
+        .collect();
+
+    let mut flags = vec![];
+    if config.mode == GameMode::CaptureTheFlag {
+        for team in Team::ALL {
+            let home = arena.flag_home(team);
+            // a wall drawn over the home of a flag has to make way for it
+            let cell = arena.cell_mut(home);
+            if cell.block.is_blocking() {
+                cell.block = Block::Grass;
+            }
+            cell.flag = Some(team);
+            flags.push(Flag {
+                team,
+                home,
+                state: FlagState::AtBase,
+            });
+        }
+    }
+
+    (arena, item_spawns, flags)
+}
 
-    let radius = radius as u16;
-    let radius_square = radius.pow(2);
+/// Direction to shoot at `to` from `from`, if it is in a lane of the weapon with no wall between
+fn aim(arena: &Arena, from: Position, to: Position, spec: &WeaponSpec) -> Option<Direction> {
+    let spread = spec.spread as i16;
+
+    Direction::ALL.into_iter().find(|&direction| {
+        (-spread..=spread).any(|lane| {
+            let lane_cell = |distance| sim::shot_cell(arena, from, direction, distance, lane);
+            let Some(distance) = (1..=spec.range).find(|&d| lane_cell(d) == Some(to)) else {
+                return false;
+            };
+
+            // the lane the target stands in has to be free of walls up to it
+            (1..=distance).all(|d| {
+                let (previous, cell) = (lane_cell(d - 1), lane_cell(d));
+                cell.is_some_and(|cell| {
+                    !arena.cell(cell).block.is_blocking()
+                        && !previous.is_some_and(|previous| arena.cuts_corner(previous, cell))
+                })
+            })
+        })
+    })
+}
 
-    let top_left = (
-        coords.0.checked_sub(radius).unwrap_or(0),
-        coords.1.checked_sub(radius).unwrap_or(0),
-    );
-    let bottom_right = (
-        min(1 + coords.0 + radius, map.height as u16),
-        min(1 + coords.1 + radius, map.width as u16),
-    );
+/// First step of a shortest path around walls and players, the target cell itself may be
+/// occupied. None if `to` can not be reached
+fn next_step(arena: &Arena, from: Position, to: Position) -> Option<Direction> {
+    let path = pathfinding::astar(arena, from, to)?;
+    pathfinding::direction_to(from, *path.first()?)
+}
 
-    assert!(top_left.0 <= bottom_right.0);
-    assert!(top_left.1 <= bottom_right.1);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game_core::protocol::ServerPacket;
+    use proto_dryb::Serialize;
+
+    /// Joins a client over loopback, the returned stream is the end the client would read
+    fn join(server: &mut Server, buf: &mut [u8], name: &str) -> (u32, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let remote = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, addr) = listener.accept().unwrap();
+        server
+            .client_joined(buf, addr, Connection::Tcp(Arc::new(stream)), name)
+            .unwrap();
+
+        (server.entities.id(addr).unwrap(), remote)
+    }
 
-    let mut res = vec![];
-    for i in top_left.0..bottom_right.0 {
-        for j in top_left.1..bottom_right.1 {
-            let x = coords.0 as i16 - i as i16;
-            let y = coords.1 as i16 - j as i16;
+    /// Everything the server has sent to the client so far
+    fn received(remote: &mut TcpStream) -> Vec<ServerPacket> {
+        remote
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        let mut bytes = vec![];
+        let mut buf = [0; BUF_SIZE_512];
+        while let Ok(n @ 1..) = remote.read(&mut buf) {
+            bytes.extend_from_slice(&buf[..n]);
+        }
 
-            // draw circle
-            if x.pow(2) + y.pow(2) <= radius_square as i16 {
-                res.push(types::MapCell {
-                    block: map.coords[i as usize][j as usize].block,
-                    coords: (i, j),
-                });
+        let mut packets = vec![];
+        let mut offset = 0;
+        while let Ok((packet, size)) = Packet::deserialize(&bytes[offset..]) {
+            if let Packet::Server(packet) = packet {
+                packets.push(packet);
             }
+            offset += size;
+        }
+
+        packets
+    }
+
+    /// Puts the player on the free cell with a fresh loadout
+    fn place(server: &mut Server, id: u32, coords: Position) {
+        server.world.respawn(id, coords);
+    }
+
+    fn occupant(server: &Server, coords: Position) -> Option<u32> {
+        server.world.arena.cell(coords).occupant()
+    }
+
+    fn client(server: &Server, id: u32) -> &Client {
+        server.entities.get(id).unwrap()
+    }
+
+    fn client_mut(server: &mut Server, id: u32) -> &mut Client {
+        server.entities.get_mut(id).unwrap()
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from((Ipv4Addr::LOCALHOST, port))
+    }
+
+    #[test]
+    fn killed_players_respawn_once_the_countdown_is_over() {
+        let mut buf = [0; BUF_SIZE_512];
+        let mut server = Server::new(Config::default());
+        let (killer, _killer_end) = join(&mut server, &mut buf, "killer");
+        let (victim, _victim_end) = join(&mut server, &mut buf, "victim");
+
+        let before = Instant::now();
+        server.player_died(killer, victim, &mut buf).unwrap();
+        let respawn_at = client(&server, victim).respawn_at.unwrap();
+        assert!(respawn_at >= before + server.config.respawn_time);
+
+        server.respawn_dead_players(&mut buf).unwrap();
+        assert!(client(&server, victim).respawn_at.is_some());
+
+        // the countdown runs out
+        client_mut(&mut server, victim).respawn_at = Some(Instant::now());
+        server.respawn_dead_players(&mut buf).unwrap();
+        assert!(client(&server, victim).respawn_at.is_none());
+        let respawned = server.world.player(victim).unwrap();
+        assert!(!respawned.dead);
+        assert_eq!(respawned.hp, PLAYER_HP);
+        assert_eq!(occupant(&server, respawned.coords), Some(victim));
+    }
+
+    #[test]
+    fn kills_and_deaths_are_scored_and_told_to_everyone() {
+        let mut buf = [0; BUF_SIZE_512];
+        let mut server = Server::new(Config::default());
+        let (killer, _killer_end) = join(&mut server, &mut buf, "killer");
+        let (victim, _victim_end) = join(&mut server, &mut buf, "victim");
+        let (_, mut bystander_end) = join(&mut server, &mut buf, "bystander");
+
+        server.player_died(killer, victim, &mut buf).unwrap();
+        server.player_died(killer, victim, &mut buf).unwrap();
+        server.player_died(victim, killer, &mut buf).unwrap();
+
+        let scores = server.scoreboard();
+        let stats = scores
+            .iter()
+            .map(|s| (s.id, s.kills, s.deaths, s.streak, s.best_streak))
+            .collect::<Vec<_>>();
+        assert_eq!(stats[..2], [(killer, 2, 1, 0, 2), (victim, 1, 2, 1, 1)]);
+
+        let feed = received(&mut bystander_end)
+            .into_iter()
+            .filter_map(|packet| match packet {
+                ServerPacket::KillFeed(feed) => Some((feed.killer, feed.victim)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(feed, [(killer, victim), (killer, victim), (victim, killer)]);
+    }
+
+    #[test]
+    fn names_are_sanitized_and_made_unique() {
+        let mut buf = [0; BUF_SIZE_512];
+        let mut server = Server::new(Config::default());
+
+        assert_eq!(sanitize_name("<b>o b!"), "bob");
+        assert_eq!(
+            sanitize_name(&"x".repeat(40)).len(),
+            constants::MAX_NAME_LEN
+        );
+        assert_eq!(server.unique_name("!!!"), DEFAULT_NAME);
+
+        let long = "y".repeat(constants::MAX_NAME_LEN);
+        let mut names = vec![];
+        let mut ends = vec![];
+        for requested in ["bob", "bob", "BOB", long.as_str(), long.as_str()] {
+            let (id, end) = join(&mut server, &mut buf, requested);
+            names.push(server.world.player(id).unwrap().name.clone());
+            ends.push(end);
+        }
+        let long2 = format!("{}2", &long[1..]);
+        assert_eq!(
+            names,
+            ["bob", "bob2", "BOB3", long.as_str(), long2.as_str()]
+        );
+    }
+
+    #[test]
+    fn chat_is_stripped_of_control_characters_and_capped() {
+        assert_eq!(
+            sanitize_chat("  hi\x07 there\n ").as_deref(),
+            Some("hi there")
+        );
+        assert_eq!(sanitize_chat(" \t\r\n "), None);
+        assert_eq!(
+            sanitize_chat(&"z".repeat(100)).map(|text| text.len()),
+            Some(constants::MAX_CHAT_LEN)
+        );
+    }
+
+    #[test]
+    fn chat_messages_are_limited_to_a_burst_and_a_rate() {
+        let mut bucket = TokenBucket::new(CHAT_BURST, CHAT_MESSAGES_PER_SEC);
+        let now = Instant::now();
+
+        for _ in 0..CHAT_BURST as usize {
+            assert!(bucket.take(now));
+        }
+        assert!(!bucket.take(now));
+        // one message more every two seconds
+        assert!(!bucket.take(now + Duration::from_secs(1)));
+        assert!(bucket.take(now + Duration::from_secs(2)));
+        assert!(!bucket.take(now + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn picked_up_items_respawn_after_a_while() {
+        let mut buf = [0; BUF_SIZE_512];
+        let mut server = Server::new(Config {
+            item_spawns: 1,
+            ..Config::default()
+        });
+        let (id, _end) = join(&mut server, &mut buf, "collector");
+        let spawn = server.item_spawns[0].coords;
+        let item = server.item_spawns[0].kind;
+        let item_at = |server: &Server| server.world.arena.cell(spawn).item;
+        // steps onto the spawn point from the side
+        let (from, direction) = match spawn.col {
+            0 => (Position::new(spawn.row, 1), Direction::Left),
+            _ => (Position::new(spawn.row, spawn.col - 1), Direction::Right),
+        };
+        place(&mut server, id, from);
+        server.spawn_items(&mut buf).unwrap();
+        assert_eq!(item_at(&server), Some(item));
+
+        let events = server.world.apply(Action::Move(id, direction));
+        server.handle_events(events, &mut buf);
+        let player = server.world.player(id).unwrap();
+        assert_eq!(player.coords, spawn);
+        assert_eq!(player.health_packs, 1);
+        assert_eq!(item_at(&server), None);
+
+        server.spawn_items(&mut buf).unwrap();
+        let respawn_at = server.item_spawns[0].respawn_at.unwrap();
+        assert!(respawn_at > Instant::now());
+
+        // nothing spawns under a player
+        server.item_spawns[0].respawn_at = Some(Instant::now());
+        server.spawn_items(&mut buf).unwrap();
+        assert_eq!(item_at(&server), None);
+
+        place(&mut server, id, from);
+        server.spawn_items(&mut buf).unwrap();
+        assert_eq!(item_at(&server), Some(item));
+    }
+
+    #[test]
+    fn flags_are_taken_returned_and_captured() {
+        let mut buf = [0; BUF_SIZE_2048];
+        let mut server = Server::new(Config {
+            mode: GameMode::CaptureTheFlag,
+            ..Config::default()
+        });
+        let (red, _red_end) = join(&mut server, &mut buf, "red");
+        let (blue, _blue_end) = join(&mut server, &mut buf, "blue");
+        assert_eq!(server.world.player(red).unwrap().team, Some(Team::Red));
+        assert_eq!(server.world.player(blue).unwrap().team, Some(Team::Blue));
+        let (red_flag, blue_flag) = (server.flags[0].home, server.flags[1].home);
+
+        place(&mut server, red, blue_flag);
+        server.touch_flags(red, &mut buf).unwrap();
+        assert_eq!(server.flags[1].state, FlagState::Carried(red));
+
+        // a dropped flag is sent home by its own team
+        let dropped_at = Position::new(blue_flag.row + 1, blue_flag.col);
+        server.drop_flags(red, dropped_at, &mut buf).unwrap();
+        assert_eq!(server.flags[1].state, FlagState::Dropped(dropped_at));
+        place(&mut server, blue, dropped_at);
+        server.touch_flags(blue, &mut buf).unwrap();
+        assert_eq!(server.flags[1].state, FlagState::AtBase);
+
+        // the enemy flag only scores while the own one is at home
+        place(&mut server, red, blue_flag);
+        server.touch_flags(red, &mut buf).unwrap();
+        server.flags[0].state = FlagState::Carried(blue);
+        place(&mut server, red, red_flag);
+        server.touch_flags(red, &mut buf).unwrap();
+        assert_eq!(server.team_scores.get(Team::Red), 0);
+
+        server.flags[0].state = FlagState::AtBase;
+        server.touch_flags(red, &mut buf).unwrap();
+        assert_eq!(server.team_scores.get(Team::Red), 1);
+        assert_eq!(server.flags[1].state, FlagState::AtBase);
+    }
+
+    #[test]
+    fn team_matches_are_won_at_the_score_limit_or_drawn_at_the_time_limit() {
+        let mut server = Server::new(Config {
+            mode: GameMode::TeamDeathmatch,
+            score_limit: Some(5),
+            ..Config::default()
+        });
+        server.team_scores = TeamScores { red: 4, blue: 2 };
+        assert_eq!(server.winner(false), None);
+        assert_eq!(server.winner(true), Some(Winner::Team(Team::Red)));
+
+        server.team_scores = TeamScores { red: 4, blue: 5 };
+        assert_eq!(server.winner(false), Some(Winner::Team(Team::Blue)));
+
+        server.team_scores = TeamScores { red: 3, blue: 3 };
+        assert_eq!(server.winner(false), None);
+        assert_eq!(server.winner(true), Some(Winner::Draw));
+    }
+
+    #[test]
+    fn free_for_all_matches_are_won_by_the_leader_unless_tied() {
+        let mut buf = [0; BUF_SIZE_512];
+        let mut server = Server::new(Config {
+            score_limit: Some(3),
+            ..Config::default()
+        });
+        assert_eq!(server.winner(false), None);
+        assert_eq!(server.winner(true), Some(Winner::Draw));
+
+        let (first, _first_end) = join(&mut server, &mut buf, "first");
+        let (second, _second_end) = join(&mut server, &mut buf, "second");
+        client_mut(&mut server, first).stats.kills = 2;
+        client_mut(&mut server, second).stats.kills = 2;
+        assert_eq!(server.winner(false), None);
+        assert_eq!(server.winner(true), Some(Winner::Draw));
+
+        client_mut(&mut server, first).stats.kills = 3;
+        assert_eq!(server.winner(false), Some(Winner::Player(first)));
+    }
+
+    #[test]
+    fn matches_end_when_the_time_is_up_and_restart_afterwards() {
+        let mut buf = [0; BUF_SIZE_2048];
+        let mut server = Server::new(Config::default());
+        let (id, _end) = join(&mut server, &mut buf, "player");
+        client_mut(&mut server, id).stats.kills = 1;
+        server.phase = MatchPhase::Live;
+        server.phase_ends_at = Some(Instant::now());
+
+        server.update_match(&mut buf).unwrap();
+        assert_eq!(server.phase, MatchPhase::PostMatch);
+
+        server.phase_ends_at = Some(Instant::now());
+        server.update_match(&mut buf).unwrap();
+        assert_eq!(server.phase, MatchPhase::Lobby);
+        assert_eq!(client(&server, id).stats.kills, 0);
+        assert!(client(&server, id).respawn_at.is_none());
+    }
+
+    #[test]
+    fn bots_forget_where_they_wandered_when_the_map_is_replaced() {
+        let mut buf = [0; BUF_SIZE_2048];
+        let mut server = Server::new(Config {
+            bots: 1,
+            ..Config::default()
+        });
+        server.balance_bots(&mut buf).unwrap();
+        let bot = server.entities.id(server.bots[0].addr).unwrap();
+        place(&mut server, bot, Position::new(0, 0));
+        let arena = &server.world.arena;
+        let corner = Position::new(arena.height as u16 - 1, arena.width as u16 - 1);
+        server.bots[0].wander_to = Some(corner);
+        assert!(matches!(server.bot_action(0), Some(ClientPacket::Move(..))));
+        assert_eq!(server.bots[0].wander_to, Some(corner));
+
+        server
+            .reset_match(Some(utils::generate_map()), &mut buf)
+            .unwrap();
+        assert_eq!(server.bots[0].wander_to, None);
+        assert!(matches!(
+            server.bot_action(0),
+            None | Some(ClientPacket::Move(..))
+        ));
+    }
+
+    #[test]
+    fn entities_look_players_up_by_address_and_id() {
+        let mut entities = Entities::default();
+        entities.insert(addr(1), Connection::Bot, Client::new(0));
+        entities.insert(addr(2), Connection::Bot, Client::new(1));
+
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities.id(addr(1)), Some(0));
+        assert_eq!(entities.id(addr(2)), Some(1));
+        assert_eq!(entities.id(addr(3)), None);
+        assert_eq!(entities.ids(), vec![0, 1]);
+    }
+
+    #[test]
+    fn removed_players_are_gone_from_every_lookup() {
+        let mut entities = Entities::default();
+        entities.insert(addr(1), Connection::Bot, Client::new(0));
+        entities.insert(addr(2), Connection::Bot, Client::new(1));
+        let token = entities.get(0).unwrap().token;
+
+        assert_eq!(entities.remove(0).map(|c| c.id), Some(0));
+        assert!(entities.get(0).is_none());
+        assert_eq!(entities.id(addr(1)), None);
+        assert_eq!(entities.session(token), None);
+        assert!(entities.send(0, &[0]).is_err());
+        assert_eq!(entities.ids(), vec![1]);
+        assert!(entities.remove(0).is_none());
+
+        // a lost connection forgets the address but keeps the player
+        assert_eq!(entities.disconnect(addr(2)), Some(1));
+        assert_eq!(entities.id(addr(2)), None);
+        assert!(entities.get(1).is_some());
+        assert!(entities.send(1, &[0]).is_err());
+    }
+
+    #[test]
+    fn lost_players_are_found_by_their_token_until_the_session_expires() {
+        let mut buf = [0; BUF_SIZE_2048];
+        let mut server = Server::new(Config::default());
+        server
+            .client_joined(&mut buf, addr(1), Connection::Bot, "lost")
+            .unwrap();
+        let id = server.entities.id(addr(1)).unwrap();
+        let token = server.entities.get(id).unwrap().token;
+        assert_eq!(server.entities.session(token), Some(id));
+        assert_eq!(server.entities.session(token.wrapping_add(1)), None);
+
+        server.client_disconnected(addr(1));
+        assert_eq!(server.entities.id(addr(1)), None);
+        assert!(server.entities.get(id).unwrap().disconnected_at.is_some());
+
+        // still within the grace the player waits to be resumed on a new address
+        server.expire_sessions(&mut buf).unwrap();
+        assert_eq!(server.entities.session(token), Some(id));
+        server.entities.reconnect(id, addr(2), Connection::Bot);
+        assert_eq!(server.entities.id(addr(2)), Some(id));
+
+        server.client_disconnected(addr(2));
+        server.config.reconnect_grace = Duration::ZERO;
+        server.expire_sessions(&mut buf).unwrap();
+        assert_eq!(server.entities.session(token), None);
+        assert!(server.entities.get(id).is_none());
+        assert!(server.world.player(id).is_none());
+    }
+
+    #[test]
+    fn connections_which_never_join_time_out() {
+        let mut buf = [0; BUF_SIZE_2048];
+        let mut server = Server::new(Config::default());
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let silent = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, addr) = listener.accept().unwrap();
+        server.client_connected(addr, Arc::new(stream));
+
+        server.heartbeat(&mut buf).unwrap();
+        assert!(server.pending.contains_key(&addr));
+
+        server.config.idle_timeout = Duration::ZERO;
+        server.heartbeat(&mut buf).unwrap();
+        assert!(!server.pending.contains_key(&addr));
+        // the server closed its side
+        assert_eq!((&silent).read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn guessing_the_admin_password_gets_the_address_banned() {
+        let mut buf = [0; BUF_SIZE_2048];
+        let mut server = Server::new(Config {
+            admin_password: Some("secret".to_string()),
+            ..Config::default()
+        });
+
+        // the right password starts the count over
+        for _ in 1..ADMIN_PASSWORD_ATTEMPTS {
+            assert_eq!(
+                server.remote_admin(addr(1), "guess", "list", &mut buf),
+                "Wrong password"
+            );
+        }
+        server.remote_admin(addr(1), "secret", "list", &mut buf);
+        for _ in 1..ADMIN_PASSWORD_ATTEMPTS {
+            server.remote_admin(addr(2), "guess", "list", &mut buf);
         }
+        assert!(server.bans.is_empty());
+
+        server.remote_admin(addr(3), "guess", "list", &mut buf);
+        assert!(server.bans.contains(&Ipv4Addr::LOCALHOST.into()));
+    }
+
+    #[test]
+    fn passwords_only_match_byte_for_byte() {
+        assert!(passwords_match("secret", "secret"));
+        assert!(!passwords_match("secret", "secreT"));
+        assert!(!passwords_match("secret", "secret!"));
+        assert!(!passwords_match("secret", ""));
     }
 
-    res
+    #[test]
+    fn packets_split_over_reads_wait_for_their_rest() {
+        let mut buf = [0; BUF_SIZE_64];
+        let n = Packet::Client(ClientPacket::Join("split".to_string()))
+            .serialize(&mut buf)
+            .unwrap();
+        let mut bytes = buf[..n].to_vec();
+        bytes.extend_from_slice(&buf[..n - 1]);
+
+        let packets = take_packets(&mut bytes);
+        assert!(matches!(
+            packets.as_slice(),
+            [Packet::Client(ClientPacket::Join(name))] if name == "split"
+        ));
+        assert_eq!(bytes, &buf[..n - 1]);
+
+        bytes.push(buf[n - 1]);
+        assert_eq!(take_packets(&mut bytes).len(), 1);
+        assert!(bytes.is_empty());
+    }
 }